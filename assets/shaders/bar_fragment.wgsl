@@ -1,5 +1,5 @@
 @group(2) @binding(0)
-var<uniform> normalized_data: array<vec4<f32>, 16>;
+var<storage, read> normalized_data: array<f32>;
 
 @group(2) @binding(1)
 var<uniform> viewport_width: f32;
@@ -13,6 +13,10 @@ var<uniform> monochrome: u32;
 @group(2) @binding(4)
 var<uniform> colors: array<vec4<f32>, 4>;
 
+// Position within the current beat, 0.0 on the beat
+@group(2) @binding(5)
+var<uniform> beat_phase: f32;
+
 fn value_to_monochrome(value: f32) -> vec4<f32> {
     // Define a grayscale value by setting all color components to the value
     let grayscale = value; // Value between 0.0 (black) and 1.0 (white)
@@ -52,15 +56,10 @@ fn fragment(
     @location(2) uv: vec2<f32>,
 ) -> @location(0) vec4<f32> {
     //return vec4<f32>(uv.x, uv.y, 0.0, 1.0);
-    var vec_index = i32(uv.x * 64.0);
-    vec_index = clamp(vec_index, 0, 63);
-
-    // Calculate which component of vec4 and which array to use
-    let array_index = vec_index / 4;
-    let component_index = vec_index % 4;
+    let num_buckets = i32(arrayLength(&normalized_data));
+    let index = clamp(i32(uv.x * f32(num_buckets)), 0, num_buckets - 1);
 
-    // Assuming normalized_data is a two-dimensional array declared as:
-    var audio_value: f32 = normalized_data[array_index][component_index];
+    var audio_value: f32 = normalized_data[index];
 
     // Calculate bar height and flip y coordinate system
     let bar_height = audio_value * 0.8; // Scale the bar height to 80%
@@ -88,9 +87,12 @@ fn fragment(
     //let edge_softness = 0.01; // Edge softness value
     //let alpha = smoothstep(0.0, edge_softness, bar_height - flipped_y);
     
+    // Bars flash on the beat and fade over the first part of it
+    let beat_pulse = pow(1.0 - beat_phase, 4.0);
+
     // Draw the bar with soft edges
     if (flipped_y <= bar_height) {
-        return color;
+        return vec4<f32>(color.rgb * (1.0 + 0.35 * beat_pulse), color.a);
     } else {
         // Make this a bright blue color to easily see hot reloading working
         return vec4<f32>(0.0, 0.0, 0.0, 1.0); // Bright blue background
@@ -0,0 +1,99 @@
+// Twelve pitch-class energies starting at C, packed four per vector
+@group(2) @binding(0)
+var<uniform> chroma: array<vec4<f32>, 3>;
+
+@group(2) @binding(1)
+var<uniform> viewport_width: f32;
+
+@group(2) @binding(2)
+var<uniform> viewport_height: f32;
+
+@group(2) @binding(3)
+var<uniform> monochrome: u32;
+
+@group(2) @binding(4)
+var<uniform> colors: array<vec4<f32>, 4>;
+
+// Position within the current beat, 0.0 on the beat
+@group(2) @binding(5)
+var<uniform> beat_phase: f32;
+
+// Pitch class of the detected key, -1 while no key is detected
+@group(2) @binding(6)
+var<uniform> key_tonic: i32;
+
+@group(2) @binding(7)
+var<uniform> key_minor: u32;
+
+const PI: f32 = 3.14159265;
+
+fn chroma_value(pitch_class: i32) -> f32 {
+    return chroma[pitch_class / 4][pitch_class % 4];
+}
+
+// Maps a pitch class to a hue so neighbouring notes get neighbouring colors
+fn pitch_class_color(pitch_class: i32) -> vec3<f32> {
+    let hue = f32(pitch_class) / 12.0;
+    let k = vec3<f32>(0.0, 2.0 / 3.0, 1.0 / 3.0);
+    return clamp(abs(fract(hue + k) * 6.0 - 3.0) - 1.0, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// True for the tonic, third and fifth of the detected key
+fn in_key_triad(pitch_class: i32) -> bool {
+    if (key_tonic < 0) {
+        return false;
+    }
+    var third = 4;
+    if (key_minor == 1u) {
+        third = 3;
+    }
+    let interval = (pitch_class - key_tonic + 12) % 12;
+    return interval == 0 || interval == third || interval == 7;
+}
+
+@fragment
+fn fragment(
+    @builtin(position) coord: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) normals: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+) -> @location(0) vec4<f32> {
+    // Work in a square space centered on the screen
+    let aspect_ratio = viewport_width / viewport_height;
+    let position = vec2<f32>((uv.x - 0.5) * aspect_ratio, 0.5 - uv.y);
+    let distance = length(position);
+
+    // C at the top, ascending clockwise
+    var angle = atan2(position.x, position.y);
+    if (angle < 0.0) {
+        angle += 2.0 * PI;
+    }
+    let pitch_class = clamp(i32(angle / (2.0 * PI) * 12.0 + 0.5) % 12, 0, 11);
+    let value = chroma_value(pitch_class);
+
+    // Center pulse that shrinks over the course of each beat
+    let pulse_radius = 0.04 + 0.04 * (1.0 - beat_phase);
+    if (distance < pulse_radius) {
+        return vec4<f32>(vec3<f32>(0.6 * (1.0 - beat_phase)), 1.0);
+    }
+
+    let inner_radius = 0.1;
+    let outer_radius = inner_radius + value * 0.3;
+
+    // Ring around the wheel marking the notes of the detected key
+    if (distance > 0.43 && distance < 0.45 && in_key_triad(pitch_class)) {
+        if ((pitch_class - key_tonic + 12) % 12 == 0) {
+            return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+        }
+        return vec4<f32>(0.5, 0.5, 0.5, 1.0);
+    }
+
+    if (distance >= inner_radius && distance <= outer_radius) {
+        if (monochrome == 1u) {
+            return vec4<f32>(vec3<f32>(value), 1.0);
+        }
+        return vec4<f32>(pitch_class_color(pitch_class) * (0.3 + 0.7 * value), 1.0);
+    }
+
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0); // Black color
+}
@@ -1,8 +1,8 @@
 @group(2) @binding(0)
-var<uniform> left_data: array<vec4<f32>, 16>;
+var<storage, read> left_data: array<f32>;
 
 @group(2) @binding(1)
-var<uniform> right_data: array<vec4<f32>, 16>;
+var<storage, read> right_data: array<f32>;
 
 @group(2) @binding(2)
 var<uniform> viewport_width: f32;
@@ -16,6 +16,10 @@ var<uniform> monochrome: u32;
 @group(2) @binding(5)
 var<uniform> colors: array<vec4<f32>, 4>;
 
+// Smoothed band energies, each 0.0 to 1.0 relative to the loudest recent band
+@group(2) @binding(8)
+var<uniform> band_energies: array<vec4<f32>, 2>;
+
 //@group(2) @binding(5)
 //var<uniform> color_start: vec4<f32>;
 //@group(2) @binding(6)
@@ -88,22 +92,21 @@ fn fragment(
 
     // Since we're now only dealing with half the circle for the data,
     // we need to double the index range to cover the full range of audio data.
-    let index = i32(angle_uv / (3.14159) * 64.0); // 64 sections in half-circle
-
-    // Calculate which component of vec4<f32> to use
-    let component_index = index % 4;
-    let array_index = index / 4;
+    let num_buckets = i32(arrayLength(&left_data));
+    let index = clamp(i32(angle_uv / (3.14159) * f32(num_buckets)), 0, num_buckets - 1); // One section per bucket in half-circle
 
     // Extract the correct audio value from the normalized_data array
     var audio_value = 0.0;
     if (uv.x > 0.5) {
-        audio_value = right_data[array_index][component_index];
+        audio_value = right_data[index];
     } else {
-        audio_value = left_data[array_index][component_index];
+        audio_value = left_data[index];
     }
 
-    // Define a radius based on the audio_value
-    let radius = 0.1 + audio_value * 0.15;
+    // Define a radius based on the audio_value, the whole circle swelling
+    // with the first two bands (sub and bass by default)
+    let low_end = max(band_energies[0].x, band_energies[0].y);
+    let radius = 0.1 + low_end * 0.04 + audio_value * 0.15;
 
     // Calculate distance from the corrected UV coordinate to the center
     let distance_to_center = distance(center, uv_corrected);
@@ -0,0 +1,136 @@
+// Time-domain samples between -1.0 and 1.0, not buckets
+@group(2) @binding(0)
+var<storage, read> left_data: array<f32>;
+
+@group(2) @binding(1)
+var<storage, read> right_data: array<f32>;
+
+@group(2) @binding(2)
+var<uniform> viewport_width: f32;
+
+@group(2) @binding(3)
+var<uniform> viewport_height: f32;
+
+@group(2) @binding(4)
+var<uniform> monochrome: u32;
+
+@group(2) @binding(5)
+var<uniform> colors: array<vec4<f32>, 4>;
+
+// Position within the current beat, 0.0 on the beat
+@group(2) @binding(6)
+var<uniform> beat_phase: f32;
+
+// Trace width in pixels
+@group(2) @binding(7)
+var<uniform> line_width: f32;
+
+@group(2) @binding(8)
+var<uniform> glow: f32;
+
+// Upper bound on the segments tested per fragment when samples are denser than pixels
+const MAX_SEGMENTS: i32 = 64;
+
+fn value_to_monochrome(value: f32) -> vec4<f32> {
+    return vec4<f32>(value, value, value, 1.0);
+}
+
+fn value_to_color(value: f32) -> vec4<f32> {
+    // Define start, middle, and end colors for the gradient
+    let start_color = vec4<f32>(colors[0].x, colors[0].y, colors[0].z, colors[0].w);
+    let middle_color = vec4<f32>(colors[1].x, colors[1].y, colors[1].z, colors[1].w);
+    let end_color = vec4<f32>(colors[2].x, colors[2].y, colors[2].z, colors[2].w);
+
+    var color: vec4<f32>;
+    if (value < 0.5) {
+        color = mix(start_color, middle_color, value * 2.0);
+    } else {
+        color = mix(middle_color, end_color, (value - 0.5) * 2.0);
+    }
+    return color;
+}
+
+fn sample_count(channel: u32) -> i32 {
+    if (channel == 0u) {
+        return i32(arrayLength(&left_data));
+    }
+    return i32(arrayLength(&right_data));
+}
+
+fn sample_at(channel: u32, index: i32) -> f32 {
+    if (channel == 0u) {
+        return left_data[index];
+    }
+    return right_data[index];
+}
+
+// Pixel position of a sample, each channel centered in its own half of the screen
+fn point_position(channel: u32, index: i32, count: i32) -> vec2<f32> {
+    let lane_height = viewport_height * 0.5;
+    let center_y = lane_height * (f32(channel) + 0.5);
+    let x = f32(index) / f32(count - 1) * viewport_width;
+    let y = center_y - sample_at(channel, index) * lane_height * 0.45;
+    return vec2<f32>(x, y);
+}
+
+fn segment_distance(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = clamp(dot(pa, ba) / max(dot(ba, ba), 0.0001), 0.0, 1.0);
+    return length(pa - ba * h);
+}
+
+// Distance in pixels from the fragment to the trace of a channel
+fn trace_distance(channel: u32, pixel: vec2<f32>, reach: f32) -> f32 {
+    let count = sample_count(channel);
+    if (count < 2) {
+        return 1.0e6;
+    }
+
+    let spacing = viewport_width / f32(count - 1);
+    let center = i32(pixel.x / spacing);
+    let span = min(i32(ceil(reach / spacing)) + 1, MAX_SEGMENTS);
+
+    var nearest = 1.0e6;
+    for (var i = max(center - span, 0); i < min(center + span, count - 1); i++) {
+        let a = point_position(channel, i, count);
+        let b = point_position(channel, i + 1, count);
+        nearest = min(nearest, segment_distance(pixel, a, b));
+    }
+    return nearest;
+}
+
+@fragment
+fn fragment(
+    @builtin(position) coord: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) normals: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+) -> @location(0) vec4<f32> {
+    let pixel = uv * vec2<f32>(viewport_width, viewport_height);
+    let channel = select(0u, 1u, uv.y >= 0.5);
+
+    // The glow falls off over a few line widths around the trace
+    let half_width = max(line_width, 0.5) * 0.5;
+    let glow_radius = half_width * 6.0;
+    let distance = trace_distance(channel, pixel, half_width + glow_radius * 3.0);
+
+    // One pixel of smoothing on the edge of the line for anti-aliasing
+    let line = 1.0 - smoothstep(half_width - 0.5, half_width + 0.5, distance);
+    let halo = glow * exp(-distance / glow_radius);
+
+    // Faint zero line per channel that brightens on the beat
+    let lane_center = viewport_height * 0.5 * (f32(channel) + 0.5);
+    let zero_line = (1.0 - smoothstep(0.0, 1.0, abs(pixel.y - lane_center)))
+        * (0.1 + 0.15 * (1.0 - beat_phase));
+
+    let intensity = clamp(max(line, halo + zero_line), 0.0, 1.0);
+    if (intensity <= 0.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0); // Black color
+    }
+
+    if (monochrome == 1u) {
+        return value_to_monochrome(intensity);
+    }
+    return vec4<f32>(value_to_color(intensity).xyz * intensity, 1.0);
+}
@@ -1,5 +1,5 @@
 @group(2) @binding(0)
-var<uniform> normalized_data: array<vec4<f32>, 16>;
+var<storage, read> normalized_data: array<f32>;
 
 @group(2) @binding(1)
 var<uniform> viewport_width: f32;
@@ -80,30 +80,25 @@ fn fragment(
         angle_uv_positive += 2.0 * 3.14159;
     }
 
-    // Determine the index based on the angle (64 sections)
-    let index = i32(angle_uv_positive / (2.0 * 3.14159) * 64.0);
-
-    // Calculate which component of vec4<f32> to use
-    let component_index = index % 4;
-    let array_index = index / 4;
+    // Determine the index based on the angle (one section per bucket)
+    let num_buckets = i32(arrayLength(&normalized_data));
+    let index = clamp(i32(angle_uv_positive / (2.0 * 3.14159) * f32(num_buckets)), 0, num_buckets - 1);
 
     // Extract the correct audio value from the normalized_data array
-    let audio_value = normalized_data[array_index][component_index];
+    let audio_value = normalized_data[index];
 
     // Define a radius based on the audio_value
     let radius = 0.1 + audio_value * 0.2;
 
     // Determine if the current UV coordinate is within the defined shape
-    let next_index = (index + 1) % 64;
-    let next_component_index = next_index % 4;
-    let next_array_index = next_index / 4;
-    let next_audio_value = normalized_data[next_array_index][next_component_index];
+    let next_index = (index + 1) % num_buckets;
+    let next_audio_value = normalized_data[next_index];
     let next_radius = 0.1 + next_audio_value * 0.2;
 
-    let angle_next = (2.0 * 3.14159 * f32(next_index) / 64.0) + 0.001; // small offset to ensure inclusion of boundary
+    let angle_next = (2.0 * 3.14159 * f32(next_index) / f32(num_buckets)) + 0.001; // small offset to ensure inclusion of boundary
     let uv_next = center + vec2<f32>(cos(angle_next), sin(angle_next)) * next_radius * aspect_ratio;
 
-    let angle_prev = (2.0 * 3.14159 * f32(index) / 64.0) - 0.001; // small offset to ensure inclusion of boundary
+    let angle_prev = (2.0 * 3.14159 * f32(index) / f32(num_buckets)) - 0.001; // small offset to ensure inclusion of boundary
     let uv_prev = center + vec2<f32>(cos(angle_prev), sin(angle_prev)) * radius * aspect_ratio;
 
     // Calculate barycentric coordinates to check if the point lies within the triangle
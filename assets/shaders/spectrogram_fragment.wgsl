@@ -0,0 +1,74 @@
+// Ring buffer of frames, one row per frame and one column per bucket
+@group(2) @binding(0)
+var history: texture_2d<f32>;
+
+@group(2) @binding(1)
+var<uniform> viewport_width: f32;
+
+@group(2) @binding(2)
+var<uniform> viewport_height: f32;
+
+@group(2) @binding(3)
+var<uniform> monochrome: u32;
+
+@group(2) @binding(4)
+var<uniform> colors: array<vec4<f32>, 4>;
+
+// Row of the history texture written last
+@group(2) @binding(5)
+var<uniform> newest_row: u32;
+
+@group(2) @binding(6)
+var<uniform> vertical: u32;
+
+fn value_to_monochrome(value: f32) -> vec4<f32> {
+    return vec4<f32>(value, value, value, 1.0);
+}
+
+fn value_to_color(value: f32) -> vec4<f32> {
+    // Define start, middle, and end colors for the gradient
+    let start_color = vec4<f32>(colors[0].x, colors[0].y, colors[0].z, colors[0].w);
+    let middle_color = vec4<f32>(colors[1].x, colors[1].y, colors[1].z, colors[1].w);
+    let end_color = vec4<f32>(colors[2].x, colors[2].y, colors[2].z, colors[2].w);
+
+    var color: vec4<f32>;
+    if (value < 0.5) {
+        color = mix(start_color, middle_color, value * 2.0);
+    } else {
+        color = mix(middle_color, end_color, (value - 0.5) * 2.0);
+    }
+    return color;
+}
+
+@fragment
+fn fragment(
+    @builtin(position) coord: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) normals: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(history));
+
+    // Horizontal scrolls from right to left with low frequencies at the bottom,
+    // vertical falls from the top with low frequencies on the left
+    var time: f32;
+    var frequency: f32;
+    if (vertical == 1u) {
+        time = uv.y;
+        frequency = uv.x;
+    } else {
+        time = 1.0 - uv.x;
+        frequency = 1.0 - uv.y;
+    }
+
+    // Age 0 is the newest row
+    let age = clamp(i32(time * f32(size.y)), 0, size.y - 1);
+    let row = (i32(newest_row) - age + size.y) % size.y;
+    let column = clamp(i32(frequency * f32(size.x)), 0, size.x - 1);
+    let value = textureLoad(history, vec2<i32>(column, row), 0).r;
+
+    if (monochrome == 1u) {
+        return value_to_monochrome(value);
+    }
+    return vec4<f32>(value_to_color(value).xyz * value, 1.0);
+}
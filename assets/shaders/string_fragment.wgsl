@@ -1,8 +1,8 @@
 @group(2) @binding(0)
-var<uniform> left_data: array<vec4<f32>, 16>;
+var<storage, read> left_data: array<f32>;
 
 @group(2) @binding(1)
-var<uniform> right_data: array<vec4<f32>, 16>;
+var<storage, read> right_data: array<f32>;
 
 @group(2) @binding(2)
 var<uniform> viewport_width: f32;
@@ -29,11 +29,6 @@ var<uniform> colors: array<vec4<f32>, 4>;
 // @group(2) @binding(6)
 // var<uniform> globals: Globals;
 
-// Corrected constants without division
-const NUM_CIRCLES: i32 = 128;
-const NUM_CIRCLES_F32: f32 = 128.0;
-const SECTION_WIDTH: f32 = 0.0078125; // Precomputed value of 1.0 / 128.0
-const NUM_CIRCLES_HALF: i32 = 64;     // Precomputed value of 128 / 2
 
 fn value_to_monochrome(value: f32) -> vec4<f32> {
     return vec4<f32>(value, value, value, 1.0);
@@ -66,28 +61,29 @@ fn fragment(
     // Correct the UV coordinates for the aspect ratio
     let uv_corrected = vec2<f32>(uv.x, -uv.y * inv_aspect_ratio);
 
+    // One circle per bucket on each side of the center
+    let num_buckets = i32(arrayLength(&left_data));
+    let section_width = 1.0 / f32(num_buckets * 2);
+
     // Calculate the center of the circle
-    let section = floor(uv.x / SECTION_WIDTH);
-    let section_center_x = (section + 0.5) * SECTION_WIDTH;
+    let section = floor(uv.x / section_width);
+    let section_center_x = (section + 0.5) * section_width;
 
     // Calculate the index for the audio data based on the x-coordinate
     let x_offset = abs(uv_corrected.x - 0.5) * 2.0;
-    let index = clamp(i32(x_offset * f32(NUM_CIRCLES_HALF)), 0, 63);
-
-    let component_index = index % 4;
-    let array_index = index / 4;
+    let index = clamp(i32(x_offset * f32(num_buckets)), 0, num_buckets - 1);
 
     // Retrieve the current audio value
     let is_right = step(0.5, uv_corrected.x);
     let audio_value = mix(
-        left_data[array_index][component_index],
-        right_data[array_index][component_index],
+        left_data[index],
+        right_data[index],
         is_right
     );
 
     // Define circle parameters
     let scaled_audio_value = -(audio_value / 5.0) + 0.6;
-    let diameter = ((audio_value * 0.8) + 0.2) * SECTION_WIDTH;
+    let diameter = ((audio_value * 0.8) + 0.2) * section_width;
     let radius = diameter * 0.5;
 
     let circle_center = vec2<f32>(section_center_x, scaled_audio_value * inv_aspect_ratio);
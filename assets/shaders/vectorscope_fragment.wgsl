@@ -0,0 +1,71 @@
+// Square persistence image of the plotted sample pairs
+@group(2) @binding(0)
+var trace: texture_2d<f32>;
+
+@group(2) @binding(1)
+var trace_sampler: sampler;
+
+@group(2) @binding(2)
+var<uniform> viewport_width: f32;
+
+@group(2) @binding(3)
+var<uniform> viewport_height: f32;
+
+@group(2) @binding(4)
+var<uniform> monochrome: u32;
+
+@group(2) @binding(5)
+var<uniform> colors: array<vec4<f32>, 4>;
+
+fn value_to_monochrome(value: f32) -> vec4<f32> {
+    return vec4<f32>(value, value, value, 1.0);
+}
+
+fn value_to_color(value: f32) -> vec4<f32> {
+    // Define start, middle, and end colors for the gradient
+    let start_color = vec4<f32>(colors[0].x, colors[0].y, colors[0].z, colors[0].w);
+    let middle_color = vec4<f32>(colors[1].x, colors[1].y, colors[1].z, colors[1].w);
+    let end_color = vec4<f32>(colors[2].x, colors[2].y, colors[2].z, colors[2].w);
+
+    var color: vec4<f32>;
+    if (value < 0.5) {
+        color = mix(start_color, middle_color, value * 2.0);
+    } else {
+        color = mix(middle_color, end_color, (value - 0.5) * 2.0);
+    }
+    return color;
+}
+
+// Faint axes and diagonals, `position` runs from -1.0 to 1.0 on both axes
+fn graticule(position: vec2<f32>, pixel_size: f32) -> f32 {
+    let axes = min(abs(position.x), abs(position.y));
+    let diagonals = min(abs(position.x - position.y), abs(position.x + position.y)) * 0.7071;
+    let distance = min(axes, diagonals);
+    return (1.0 - smoothstep(0.0, pixel_size * 1.5, distance)) * 0.15;
+}
+
+@fragment
+fn fragment(
+    @builtin(position) coord: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) normals: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+) -> @location(0) vec4<f32> {
+    // Fit the square scope into the middle of the window
+    let side = min(viewport_width, viewport_height);
+    let pixel = uv * vec2<f32>(viewport_width, viewport_height);
+    let offset = (vec2<f32>(viewport_width, viewport_height) - side) * 0.5;
+    let scope_uv = (pixel - offset) / side;
+    if (any(scope_uv < vec2<f32>(0.0)) || any(scope_uv > vec2<f32>(1.0))) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0); // Black color
+    }
+
+    let value = textureSample(trace, trace_sampler, scope_uv).r;
+    let position = vec2<f32>(scope_uv.x * 2.0 - 1.0, 1.0 - scope_uv.y * 2.0);
+    let intensity = clamp(max(value, graticule(position, 2.0 / side)), 0.0, 1.0);
+
+    if (monochrome == 1u) {
+        return value_to_monochrome(intensity);
+    }
+    return vec4<f32>(value_to_color(intensity).xyz * intensity, 1.0);
+}
@@ -1,8 +1,8 @@
 @group(2) @binding(0)
-var<uniform> left_data: array<vec4<f32>, 16>;
+var<storage, read> left_data: array<f32>;
 
 @group(2) @binding(1)
-var<uniform> right_data: array<vec4<f32>, 16>;
+var<storage, read> right_data: array<f32>;
 
 @group(2) @binding(2)
 var<uniform> viewport_width: f32;
@@ -16,6 +16,10 @@ var<uniform> monochrome: u32;
 @group(2) @binding(5)
 var<uniform> colors: array<vec4<f32>, 4>;
 
+// Spectral centroid, spread, rolloff and flatness, then flux, each around 0.0 to 1.0
+@group(2) @binding(7)
+var<uniform> spectral_features: array<vec4<f32>, 2>;
+
 //@group(2) @binding(5)
 //var<uniform> color_start: vec4<f32>;
 //@group(2) @binding(6)
@@ -91,17 +95,13 @@ fn fragment(
     var color: vec4<f32> = vec4<f32>(0.0, 0.0, 0.0, 1.0);
 
     // Initialize audio_value before using it to calculate twist_offset
+    let num_buckets = i32(arrayLength(&left_data));
+    let index = clamp(i32((abs(uv_corrected.x - 0.5) * 2.0) * f32(num_buckets)), 0, num_buckets - 1);
     var audio_value = 0.0;
     if (uv_corrected.x > 0.5) {
-        let index = i32((abs(uv_corrected.x - 0.5) * 2.0) * f32(num_strands / 2));
-        let component_index = index % 4;
-        let array_index = index / 4;
-        audio_value = right_data[array_index][component_index];
+        audio_value = right_data[index];
     } else {
-        let index = i32((abs(uv_corrected.x - 0.5) * 2.0) * f32(num_strands / 2));
-        let component_index = index % 4;
-        let array_index = index / 4;
-        audio_value = left_data[array_index][component_index];
+        audio_value = left_data[index];
     }
 
     // Now calculate the twist_offset using the initialized audio_value
@@ -116,24 +116,30 @@ fn fragment(
 
     let alpha = mix(0.1, 0.8, audio_value);
 
+    // Brighter sounds shift the strands up the color gradient, noisier ones
+    // spread the glow between them
+    let centroid = spectral_features[0].x;
+    let flatness = spectral_features[0].w;
+    let color_value = clamp(audio_value * (0.75 + 0.5 * centroid), 0.0, 1.0);
+
     // Improved gradient calculation
     let gradient = uv_corrected.y * 0.5 + 0.5;
     
     // Glow effect
-    let glow = exp(-10.0 * abs(uv_corrected.y - (top_strand + bottom_strand) * 0.5));
+    let glow = exp(-mix(14.0, 4.0, flatness) * abs(uv_corrected.y - (top_strand + bottom_strand) * 0.5));
 
     if (abs(uv_corrected.y - top_strand) < 0.001 || abs(uv_corrected.y - bottom_strand) < 0.001) {
         if (monochrome == 1u) {
             color = value_to_monochrome(audio_value);
         } else {
-            color = value_to_color(audio_value);
+            color = value_to_color(color_value);
         }
         color.a = alpha;
     } else if (uv_corrected.y < top_strand - 0.001 && uv_corrected.y > bottom_strand + 0.001) {
         if (monochrome == 1u) {
             color = value_to_monochrome(audio_value);
         } else {
-            color = value_to_color(audio_value);
+            color = value_to_color(color_value);
         }
         color.a = alpha * glow;
     }
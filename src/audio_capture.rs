@@ -11,7 +11,7 @@ use std::sync::{
 };
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::visualization::VisualizationType;
 
@@ -19,6 +19,8 @@ use crate::visualization::VisualizationType;
 pub struct AudioProcessedEvent {
     pub left: Vec<f32>,
     pub right: Vec<f32>,
+    pub sample_rate: u32,
+    pub timestamp: Instant,
 }
 
 #[derive(Debug, PartialEq)]
@@ -106,6 +108,7 @@ pub fn stream_input(
         let supported_buffer_size: cpal::SupportedBufferSize =
             config_clone.buffer_size().to_owned();
         let channels = config_clone.channels();
+        let sample_rate = config_clone.sample_rate().0;
         println!("Config used has {} channels", channels);
 
         let stream = device
@@ -142,6 +145,8 @@ pub fn stream_input(
                             let audio_event = AudioProcessedEvent {
                                 left: left_channel,
                                 right: right_channel,
+                                sample_rate,
+                                timestamp: Instant::now(),
                             };
 
 
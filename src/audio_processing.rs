@@ -1,335 +1,415 @@
 use bevy::prelude::*;
-use bevy::utils::Duration;
 use bevy::window::PrimaryWindow;
 
-use crate::audio_capture::AudioReceiver;
-use crate::ARRAY_UNIFORM_SIZE;
-use crate::NUM_BUCKETS;
+use crate::audio_capture::{AudioProcessedEvent, AudioReceiver};
+use crate::cfg::{ChannelSource, TwoChannelMode};
+use crate::dsp_worker::{AnalysisSettings, AnalyzedFrame, DspWorker};
+use crate::fft::bucket_edges;
+use crate::pipeline::{Pipeline, StageContext, StageData, StagePhase};
+use crate::{MAX_BUCKETS, MIN_BUCKETS};
 
-use crate::materials::{
-    BarMaterial, CircleSplitMaterial, PolygonMaterial, StringMaterial, WaveMaterial,
-};
+use crate::tempo::TempoEstimate;
+use crate::visualization::VisualizerMaterials;
 use crate::VisualizationType;
 use crate::{CfgResource, MyConfig};
-use spectrum_analyzer::windows::hann_window; // Import the window function
+use std::mem;
+use std::time::Instant;
+
+/// Buckets of the mono mix of a freshly analyzed audio buffer, straight from
+/// the analyzer: before weighting, smoothing, the noise floor or any
+/// animation, and the same whatever the two-channel mode.
+#[derive(Debug, Clone)]
+pub struct SpectrumFrame {
+    pub mono: Vec<f32>,
+    /// Energy of the twelve pitch classes starting at C, from the FFT bins
+    pub chroma: [f32; 12],
+    pub timestamp: Instant,
+}
+
+/// Every spectrum analyzed since the last update, oldest first. Refilled by
+/// `audio_event_system` on every update into the buffers of earlier updates,
+/// so the systems running after it see each frame once without allocating.
+#[derive(Resource, Default)]
+pub struct SpectrumFrames {
+    frames: Vec<SpectrumFrame>,
+    len: usize,
+}
+
+impl SpectrumFrames {
+    pub fn iter(&self) -> impl Iterator<Item = &SpectrumFrame> {
+        self.frames[..self.len].iter()
+    }
 
-use spectrum_analyzer::{samples_fft_to_spectrum, FrequencyLimit, FrequencySpectrum};
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn push(&mut self, mono: &[f32], chroma: [f32; 12], timestamp: Instant) {
+        if self.len == self.frames.len() {
+            self.frames.push(SpectrumFrame {
+                mono: Vec::new(),
+                chroma,
+                timestamp,
+            });
+        }
+        let frame = &mut self.frames[self.len];
+        frame.mono.clear();
+        frame.mono.extend_from_slice(mono);
+        frame.chroma = chroma;
+        frame.timestamp = timestamp;
+        self.len += 1;
+    }
+}
 
+/// Latest analysis results and the display stages animating and normalizing
+/// them for the materials.
 #[derive(Resource)]
 pub struct AudioVisualizerState {
-    previous_buckets_left: Vec<f32>,
-    previous_buckets_right: Vec<f32>,
+    target_buckets_left: Vec<f32>,
+    target_buckets_right: Vec<f32>,
+    display_left: StageData,
+    display_right: StageData,
+    pipeline_left: Pipeline,
+    pipeline_right: Pipeline,
+    // Frames received from the worker, and the channel picked by a
+    // single-channel visualizer
+    frames: Vec<AnalyzedFrame>,
+    mono_buckets: Vec<f32>,
+    // Settings last sent to the worker; the GUI marks the configuration as
+    // changed on every frame it is open
+    settings: Option<AnalysisSettings>,
 }
 
 impl AudioVisualizerState {
     pub fn new(num_buckets: usize) -> Self {
         AudioVisualizerState {
-            previous_buckets_left: vec![0.0; num_buckets],
-            previous_buckets_right: vec![0.0; num_buckets],
+            target_buckets_left: vec![0.0; num_buckets],
+            target_buckets_right: vec![0.0; num_buckets],
+            display_left: StageData::default(),
+            display_right: StageData::default(),
+            pipeline_left: Pipeline::new(StagePhase::Display..=StagePhase::Display),
+            pipeline_right: Pipeline::new(StagePhase::Display..=StagePhase::Display),
+            frames: Vec::new(),
+            mono_buckets: Vec::new(),
+            settings: None,
         }
     }
+}
 
-    fn animate_buckets(
-        &mut self,
-        current_buckets: &[f32],
-        interpolation_factor: f32,
-        is_left_channel: bool,
-        config: &MyConfig,
-    ) -> Vec<f32> {
-        let previous_buckets = if is_left_channel {
-            &mut self.previous_buckets_left
-        } else {
-            &mut self.previous_buckets_right
-        };
-
-        let mut animated_buckets = Vec::with_capacity(current_buckets.len());
-
-        if current_buckets.iter().fold(0.0, |sum, &val| sum + val) <= config.gate_threshold {
-            animated_buckets = vec![0.0; current_buckets.len()];
-        } else {
-            for (&current, previous) in current_buckets.iter().zip(previous_buckets.iter_mut()) {
-                // Interpolate between the previous bucket value and the current one
-                let interpolated_value = *previous + (current - *previous) * interpolation_factor;
-                animated_buckets.push(interpolated_value);
-                // Update the previous value for the next frame
-                *previous = interpolated_value;
+// The two signals analyzed for the left and right buckets; mid and side are
+// computed into the reusable `mid_side` buffers. Harmonic and percussive
+// buckets are both separated from the mono mix, which is returned twice.
+pub fn analysis_channels<'a>(
+    audio_event: &'a AudioProcessedEvent,
+    mode: TwoChannelMode,
+    mid_side: &'a mut (Vec<f32>, Vec<f32>),
+) -> (&'a [f32], &'a [f32]) {
+    match mode {
+        TwoChannelMode::LeftRight => (&audio_event.left, &audio_event.right),
+        TwoChannelMode::MidSide => {
+            let (mid, side) = mid_side;
+            mid.clear();
+            side.clear();
+            for (left, right) in audio_event.left.iter().zip(audio_event.right.iter()) {
+                mid.push((left + right) * 0.5);
+                side.push((left - right) * 0.5);
             }
+            (mid, side)
+        }
+        TwoChannelMode::HarmonicPercussive => {
+            let (mono, _) = mid_side;
+            mono.clear();
+            mono.extend(
+                audio_event
+                    .left
+                    .iter()
+                    .zip(audio_event.right.iter())
+                    .map(|(left, right)| (left + right) * 0.5),
+            );
+            let mono: &'a [f32] = mono;
+            (mono, mono)
         }
-
-        animated_buckets
     }
 }
 
-fn is_power_of_two(number: usize) -> bool {
-    number != 0 && (number & (number - 1)) == 0
+// Fraction of the remaining distance a one-pole follower with the given
+// time constant covers in `elapsed_seconds`.
+pub fn smoothing_coefficient(time_ms: f32, elapsed_seconds: f32) -> f32 {
+    if time_ms <= 0.0 {
+        return 1.0;
+    }
+    1.0 - (-elapsed_seconds * 1000.0 / time_ms).exp()
 }
 
-// Entry function for the audio event system
+// Entry function for the audio event system. The spectrum itself is analyzed
+// by the DSP worker; this only runs the display stages and uploads the
+// finished frames.
 pub fn audio_event_system(
     audio_receiver: Res<AudioReceiver>,
-    mut bar_material: ResMut<Assets<BarMaterial>>,
-    mut string_material: ResMut<Assets<StringMaterial>>,
-    mut circle_split_material: ResMut<Assets<CircleSplitMaterial>>,
-    mut polygon_material: ResMut<Assets<PolygonMaterial>>,
-    mut wave_material: ResMut<Assets<WaveMaterial>>,
+    dsp_worker: Res<DspWorker>,
+    mut materials: VisualizerMaterials,
     primary_window: Query<&Window, With<PrimaryWindow>>,
     mut visualizer_state: ResMut<AudioVisualizerState>,
     visualization_type: Res<VisualizationType>,
     config: Res<CfgResource>,
+    time: Res<Time>,
+    mut spectrum_frames: ResMut<SpectrumFrames>,
+    mut sample_events: EventWriter<AudioProcessedEvent>,
+    tempo: Res<TempoEstimate>,
 ) {
+    // Keep the worker on the current capture channel and settings; the
+    // configuration is compared with the last one sent and only copied when
+    // a value actually changed
+    if audio_receiver.is_changed() {
+        dsp_worker.set_capture(audio_receiver.receiver.clone());
+    }
+    let channel_mode = analysis_mode(&config.0, &visualization_type);
+    let outdated = match &visualizer_state.settings {
+        Some(settings) => settings.channel_mode != channel_mode || settings.config != config.0,
+        None => true,
+    };
+    if outdated {
+        visualizer_state.pipeline_left.configure(&config.0);
+        visualizer_state.pipeline_right.configure(&config.0);
+        let settings = AnalysisSettings {
+            config: config.0.clone(),
+            channel_mode,
+        };
+        dsp_worker.configure(settings.clone());
+        visualizer_state.settings = Some(settings);
+    }
+
+    spectrum_frames.clear();
     if let Some(window) = primary_window.iter().next() {
         let window_size = Vec2::new(window.width(), window.height());
 
         if window_size.x > 0.0 && window_size.y > 0.0 {
-            if let Ok(audio_event) = audio_receiver
-                .receiver
-                .lock()
-                .unwrap()
-                .recv_timeout(Duration::new(0, 100))
-            {
-                // Flatten the audio samples into a single Vec<f32>
-                let left_samples = audio_event.left.to_vec();
-                let right_samples = audio_event.right.to_vec();
-
-                let left_buckets =
-                    samples_to_buckets(config.0.clone(), left_samples, &mut visualizer_state, true)
-                        .unwrap();
-                let right_buckets = samples_to_buckets(
-                    config.0.clone(),
-                    right_samples,
-                    &mut visualizer_state,
-                    false,
-                )
-                .unwrap();
-                //println!("{:#?}", right_buckets);
-
-                // Update visualizer materials with normalized buckets
-                update_visualizer_materials(
-                    &left_buckets,
-                    &right_buckets,
-                    &window_size,
-                    &visualization_type,
-                    &mut bar_material,
-                    &mut string_material,
-                    &mut circle_split_material,
-                    &mut polygon_material,
-                    &mut wave_material,
-                );
+            let elapsed_seconds = time.delta_seconds();
+            let mut frames = mem::take(&mut visualizer_state.frames);
+            dsp_worker.receive_frames(&mut frames);
+
+            // Start over when the bucket count was changed from the GUI
+            let num_buckets = config.0.num_buckets.clamp(MIN_BUCKETS, MAX_BUCKETS);
+            if visualizer_state.target_buckets_left.len() != num_buckets {
+                visualizer_state.target_buckets_left = vec![0.0; num_buckets];
+                visualizer_state.target_buckets_right = vec![0.0; num_buckets];
             }
-        }
-    }
-}
-
-fn samples_to_buckets(
-    config: MyConfig,
-    mut samples: Vec<f32>,
-    visualizer_state: &mut ResMut<AudioVisualizerState>,
-    is_left_channel: bool,
-) -> Option<[Vec4; ARRAY_UNIFORM_SIZE]> {
-    // Apply a window function to the samples
-    samples = hann_window(&samples);
-
-    // Ensure the sample length is a power of two, pad with zeroes if necessary
-    if !is_power_of_two(samples.len()) {
-        let next_power_of_two = samples.len().next_power_of_two();
-        samples.resize(next_power_of_two, 0.0);
-    }
-
-    // Compute the frequency spectrum using the spectrum_analyzer crate
-    let spectrum_result = samples_fft_to_spectrum(
-        &samples,                                                          // windowed samples
-        48000, // Replace with the actual sample rate of your audio
-        FrequencyLimit::Range(config.frequency_min, config.frequency_max), // Adjust the frequency range as needed
-        None, //Some(&divide_by_N_sqrt),             // Normalization function
-    );
-
-    if let Ok(spectrum) = spectrum_result {
-        // Transform the frequency spectrum into buckets for visualization
-        let mut buckets = transform_spectrum_to_buckets(&spectrum, NUM_BUCKETS);
-
-        // Apply smoothing to the buckets
-        let smoothing = config.smoothing;
-        let smoothing_size = config.smoothing_size;
-        smooth(&mut buckets, smoothing, smoothing_size);
-
-        //let amplification_factor = 1.5;
-        //amplify_differences(&mut buckets, amplification_factor);
-
-        // add a gate
-        gate(&mut buckets, config.gate_threshold);
-
-        // Animate the transition of buckets
-        let interpolation_factor = config.interpolation_factor; // Adjust this value as needed
-        let animated_buckets = visualizer_state.animate_buckets(
-            &buckets,
-            interpolation_factor,
-            is_left_channel,
-            &config,
-        );
 
-        // Normalize the animated buckets for visualization
-        let normalized_buckets = normalize_buckets(&animated_buckets);
-        Some(normalized_buckets)
-    } else {
-        println!("Spectrum analysis failed");
-        None
-    }
-}
-
-fn gate(buckets: &mut Vec<f32>, gate_threshold: f32) {
-    let len = buckets.len();
-    let mut count = 0;
-    let mut max = f32::MIN;
-
-    // Find the count of elements below the threshold and the maximum value
-    for freq in buckets.iter() {
-        if *freq < gate_threshold {
-            count += 1;
-        }
-        if *freq > max {
-            max = *freq;
-        }
-    }
+            let context = StageContext {
+                elapsed_seconds,
+                target_age_seconds: frames
+                    .last()
+                    .map_or(0.0, |frame| frame.timestamp.elapsed().as_secs_f32()),
+            };
+
+            for mut frame in frames.drain(..) {
+                // Frames analyzed before a bucket count change are dropped, the
+                // replaced targets go back to the worker to be filled again
+                if let Some(left_buckets) = frame.left.filter(|b| b.len() == num_buckets) {
+                    dsp_worker.recycle(mem::replace(
+                        &mut visualizer_state.target_buckets_left,
+                        left_buckets,
+                    ));
+                }
+                if let Some(right_buckets) = frame.right.filter(|b| b.len() == num_buckets) {
+                    dsp_worker.recycle(mem::replace(
+                        &mut visualizer_state.target_buckets_right,
+                        right_buckets,
+                    ));
+                }
 
-    // If the count is greater than the specified threshold, modify the vector
-    if count > len - (len / 8) {
-        let threshold = max - gate_threshold;
-        for freq in buckets.iter_mut() {
-            if *freq > threshold || *freq < gate_threshold {
-                *freq = 0.0;
+                if let Some(mono) = frame.mono {
+                    if mono.len() == num_buckets {
+                        spectrum_frames.push(&mono, frame.chroma, frame.timestamp);
+                    }
+                    dsp_worker.recycle(mono);
+                }
+                // Every captured buffer is passed on to the level meters
+                sample_events.send_batch(frame.audio.drain(..));
+                dsp_worker.recycle_batch(frame.audio);
+            }
+            visualizer_state.frames = frames;
+
+            // Animate and normalize the buckets for visualization
+            let state = &mut *visualizer_state;
+            state
+                .display_left
+                .buckets
+                .clone_from(&state.target_buckets_left);
+            state
+                .display_right
+                .buckets
+                .clone_from(&state.target_buckets_right);
+            state
+                .pipeline_left
+                .process(&mut state.display_left, &context);
+            state
+                .pipeline_right
+                .process(&mut state.display_right, &context);
+
+            // Update visualizer materials with the displayed buckets
+            match mono_channel_source(&config.0, &visualization_type) {
+                Some(source) => select_channel(
+                    &state.display_left.buckets,
+                    &state.display_right.buckets,
+                    source,
+                    &mut state.mono_buckets,
+                ),
+                None => state.mono_buckets.clone_from(&state.display_left.buckets),
             }
+            update_visualizer_materials(
+                &state.mono_buckets,
+                &state.display_left.buckets,
+                &state.display_right.buckets,
+                &window_size,
+                tempo.beat_phase,
+                &visualization_type,
+                &mut materials,
+            );
         }
     }
 }
 
 fn update_visualizer_materials(
-    left_buckets: &[Vec4; ARRAY_UNIFORM_SIZE],
-    right_buckets: &[Vec4; ARRAY_UNIFORM_SIZE],
+    mono_buckets: &[f32],
+    left_buckets: &[f32],
+    right_buckets: &[f32],
     window_size: &Vec2,
+    beat_phase: f32,
     visualization_type: &VisualizationType,
-    bar_material: &mut ResMut<Assets<BarMaterial>>,
-    string_material: &mut ResMut<Assets<StringMaterial>>,
-    circle_split_material: &mut ResMut<Assets<CircleSplitMaterial>>,
-    polygon_material: &mut ResMut<Assets<PolygonMaterial>>,
-    wave_material: &mut ResMut<Assets<WaveMaterial>>,
+    materials: &mut VisualizerMaterials,
 ) {
-    let mono_buckets = if needs_mono(visualization_type) {
-        mix_mono_channels(left_buckets, right_buckets)
-    } else {
-        *left_buckets
-    };
-
     match visualization_type {
         VisualizationType::Bar => {
-            for (_, material) in bar_material.iter_mut() {
-                material.normalized_data = mono_buckets;
+            for (_, material) in materials.bar_material.iter_mut() {
+                material.normalized_data.clear();
+                material.normalized_data.extend_from_slice(mono_buckets);
                 material.viewport_width = window_size.x;
                 material.viewport_height = window_size.y;
+                material.beat_phase = beat_phase;
             }
         }
         VisualizationType::String => {
-            for (_, material) in string_material.iter_mut() {
-                material.left_data = *left_buckets;
-                material.right_data = *right_buckets;
+            for (_, material) in materials.string_material.iter_mut() {
+                material.left_data.clear();
+                material.left_data.extend_from_slice(left_buckets);
+                material.right_data.clear();
+                material.right_data.extend_from_slice(right_buckets);
                 material.viewport_width = window_size.x;
                 material.viewport_height = window_size.y;
+                material.beat_phase = beat_phase;
             }
         }
         VisualizationType::CircleSplit => {
-            for (_, material) in circle_split_material.iter_mut() {
-                material.left_data = *left_buckets;
-                material.right_data = *right_buckets;
+            for (_, material) in materials.circle_split_material.iter_mut() {
+                material.left_data.clear();
+                material.left_data.extend_from_slice(left_buckets);
+                material.right_data.clear();
+                material.right_data.extend_from_slice(right_buckets);
                 material.viewport_width = window_size.x;
                 material.viewport_height = window_size.y;
+                material.beat_phase = beat_phase;
             }
         }
         VisualizationType::Wave => {
-            for (_, material) in wave_material.iter_mut() {
-                material.left_data = *left_buckets;
-                material.right_data = *right_buckets;
+            for (_, material) in materials.wave_material.iter_mut() {
+                material.left_data.clear();
+                material.left_data.extend_from_slice(left_buckets);
+                material.right_data.clear();
+                material.right_data.extend_from_slice(right_buckets);
                 material.viewport_width = window_size.x;
                 material.viewport_height = window_size.y;
+                material.beat_phase = beat_phase;
             }
         }
         VisualizationType::Polygon => {
-            for (_, material) in polygon_material.iter_mut() {
-                material.normalized_data = mono_buckets;
+            for (_, material) in materials.polygon_material.iter_mut() {
+                material.normalized_data.clear();
+                material.normalized_data.extend_from_slice(mono_buckets);
                 material.viewport_width = window_size.x;
                 material.viewport_height = window_size.y;
+                material.beat_phase = beat_phase;
             }
         }
+        // These are fed by their own systems from the chroma, waveform,
+        // spectrum frame and raw sample data
+        VisualizationType::Chroma
+        | VisualizationType::Oscilloscope
+        | VisualizationType::Spectrogram
+        | VisualizationType::Vectorscope => {}
     }
 }
 
-fn mix_mono_channels(
-    left_buckets: &[Vec4; ARRAY_UNIFORM_SIZE],
-    right_buckets: &[Vec4; ARRAY_UNIFORM_SIZE],
-) -> [Vec4; ARRAY_UNIFORM_SIZE] {
-    let mut mixed_buckets = [Vec4::ZERO; ARRAY_UNIFORM_SIZE];
-    for (i, (left, right)) in left_buckets.iter().zip(right_buckets.iter()).enumerate() {
-        mixed_buckets[i] = (*left + *right) * 0.5;
+// Channel shown by a single-channel visualizer, `None` for the others
+fn mono_channel_source(
+    config: &MyConfig,
+    visualization_type: &VisualizationType,
+) -> Option<ChannelSource> {
+    match visualization_type {
+        VisualizationType::Bar => Some(config.bar_channel),
+        VisualizationType::Polygon => Some(config.polygon_channel),
+        _ => None,
     }
-    mixed_buckets
-}
-
-fn needs_mono(visualization_type: &VisualizationType) -> bool {
-    matches!(
-        visualization_type,
-        VisualizationType::Bar | VisualizationType::Polygon
-    )
 }
 
-fn transform_spectrum_to_buckets(spectrum: &FrequencySpectrum, num_buckets: usize) -> Vec<f32> {
-    let mut buckets = vec![0f32; num_buckets];
-
-    // Determine the logarithmic scale factor based on the max frequency
-    let max_frequency = spectrum.max_fr().val();
-    let min_frequency = spectrum.min_fr().val(); // Assume there's a min_fr() or set a reasonable minimum frequency like 20.0
-    let log_min_frequency = min_frequency.ln();
-    let log_max_frequency = max_frequency.ln();
-
-    for &(frequency, value) in spectrum.data() {
-        let freq_val = frequency.val();
-
-        // Skip frequencies lower than the minimum frequency (e.g., 20 Hz)
-        if freq_val < min_frequency {
-            continue;
-        }
-
-        // Calculate the bucket index on a logarithmic scale
-        let log_freq = freq_val.ln();
-        let scale = (log_freq - log_min_frequency) / (log_max_frequency - log_min_frequency);
-        let bucket_index = (scale * (num_buckets as f32 - 1.0)) as usize;
-
-        // Add the magnitude to the appropriate bucket
-        buckets[bucket_index] += value.val();
+// Single-channel visualizers analyze mid/side exactly when their source needs
+// it, the two-channel mode only applies to the two-channel visualizers
+fn analysis_mode(config: &MyConfig, visualization_type: &VisualizationType) -> TwoChannelMode {
+    match mono_channel_source(config, visualization_type) {
+        Some(ChannelSource::Mid | ChannelSource::Side) => TwoChannelMode::MidSide,
+        Some(_) => TwoChannelMode::LeftRight,
+        None => config.two_channel_mode,
     }
-
-    buckets
 }
 
-fn normalize_buckets(buckets: &[f32]) -> [Vec4; ARRAY_UNIFORM_SIZE] {
-    let max_value = buckets.iter().cloned().fold(f32::MIN, f32::max);
-    let mut normalized_buckets = [Vec4::ZERO; ARRAY_UNIFORM_SIZE];
-
-    for (i, &value) in buckets.iter().enumerate() {
-        let vec_index = i / 4;
-        let component_index = i % 4; // This will give you component indices 0 to 3
-        if vec_index < normalized_buckets.len() {
-            // Normalize and assign the bucket value to the corresponding Vec4 component
-            normalized_buckets[vec_index][component_index] = value / max_value;
+// Picks the buckets of a single-channel visualizer from the two analyzed
+// channels, which hold mid and side for the `Mid` and `Side` sources
+fn select_channel(
+    left_buckets: &[f32],
+    right_buckets: &[f32],
+    source: ChannelSource,
+    buckets: &mut Vec<f32>,
+) {
+    let pairs = left_buckets.iter().zip(right_buckets.iter());
+    match source {
+        ChannelSource::Left | ChannelSource::Mid => {
+            buckets.clear();
+            buckets.extend_from_slice(left_buckets);
+        }
+        ChannelSource::Right | ChannelSource::Side => {
+            buckets.clear();
+            buckets.extend_from_slice(right_buckets);
+        }
+        ChannelSource::Mono => {
+            buckets.clear();
+            buckets.extend(pairs.map(|(left, right)| (left + right) * 0.5));
+        }
+        ChannelSource::Max => {
+            buckets.clear();
+            buckets.extend(pairs.map(|(left, right)| left.max(*right)));
         }
     }
+}
 
-    normalized_buckets
+// Center of a bucket's frequency range on the logarithmic scale used by the
+// analyzers; the last bucket only holds the upper limit.
+pub fn bucket_center_frequency(
+    index: usize,
+    num_buckets: usize,
+    frequency_min: f32,
+    frequency_max: f32,
+) -> f32 {
+    let (lower, upper) = bucket_edges(index, num_buckets, frequency_min, frequency_max);
+    lower * (upper / lower).sqrt()
 }
 
-fn smooth(buffer: &mut Vec<f32>, smoothing: u32, smoothing_size: u32) {
+pub fn smooth(buffer: &mut [f32], temp_buffer: &mut Vec<f32>, smoothing: u32, smoothing_size: u32) {
     let gaussian_weight =
         |distance: f32| -> f32 { (-distance.powi(2) / (2.0 * smoothing_size as f32)).exp() };
 
     for _ in 0..smoothing {
-        let temp_buffer = buffer.clone();
+        temp_buffer.clear();
+        temp_buffer.extend_from_slice(buffer);
 
         for i in 0..buffer.len() {
             let mut weighted_sum = 0.0;
@@ -346,3 +426,39 @@ fn smooth(buffer: &mut Vec<f32>, smoothing: u32, smoothing_size: u32) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft::bucket_index;
+
+    #[test]
+    fn bucket_centers_lie_inside_their_buckets() {
+        for num_buckets in [MIN_BUCKETS, 64, 333, MAX_BUCKETS] {
+            for (frequency_min, frequency_max) in [(20.0, 20_000.0), (55.0, 7_040.0)] {
+                for i in 0..num_buckets {
+                    let (lower, upper) = bucket_edges(i, num_buckets, frequency_min, frequency_max);
+                    let center =
+                        bucket_center_frequency(i, num_buckets, frequency_min, frequency_max);
+                    assert!(
+                        lower <= center && center <= upper,
+                        "bucket {} of {}: {} outside {}..{}",
+                        i,
+                        num_buckets,
+                        center,
+                        lower,
+                        upper
+                    );
+                    // The bucketizer puts a bin at the center into the same bucket
+                    let index = bucket_index(
+                        center.ln(),
+                        frequency_min.ln(),
+                        frequency_max.ln(),
+                        num_buckets,
+                    );
+                    assert_eq!(index, i, "bucket {} of {}", i, num_buckets);
+                }
+            }
+        }
+    }
+}
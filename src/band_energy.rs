@@ -0,0 +1,116 @@
+use bevy::prelude::*;
+use std::time::Instant;
+
+use crate::audio_processing::{bucket_center_frequency, smoothing_coefficient, SpectrumFrames};
+use crate::cfg::EnergyBand;
+use crate::{CfgResource, MyConfig};
+
+/// Bands passed to the shaders, in two vectors of four. The config and the GUI
+/// never hold more.
+pub const MAX_SHADER_BANDS: usize = 8;
+
+// Levels are relative to a slowly released peak of all bands, so the balance
+// between the bands survives
+const PEAK_RELEASE_MS: f32 = 5000.0;
+
+/// Levels of the first `MAX_SHADER_BANDS` bands as the shaders read them.
+pub type BandEnergiesUniform = [Vec4; 2];
+
+/// Envelope-smoothed energy of every configured band, in configuration order.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct BandEnergies {
+    /// Summed bucket power inside each band
+    pub energies: Vec<f32>,
+    /// Amplitude of each band from 0.0 to 1.0 relative to the loudest band of
+    /// the last few seconds
+    pub levels: Vec<f32>,
+}
+
+impl BandEnergies {
+    /// Levels of the bands for the shaders, zero for missing ones.
+    pub fn uniform(&self) -> BandEnergiesUniform {
+        let mut levels = [0.0; MAX_SHADER_BANDS];
+        for (uniform_level, level) in levels.iter_mut().zip(self.levels.iter()) {
+            *uniform_level = *level;
+        }
+        [
+            Vec4::from_slice(&levels[..4]),
+            Vec4::from_slice(&levels[4..]),
+        ]
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct BandEnergyTracker {
+    peak: f32,
+    last_frame: Option<Instant>,
+}
+
+impl BandEnergyTracker {
+    fn process(
+        &mut self,
+        buckets: &[f32],
+        timestamp: Instant,
+        config: &MyConfig,
+        bands: &mut BandEnergies,
+    ) {
+        let elapsed_seconds = match self.last_frame {
+            Some(last) => timestamp.saturating_duration_since(last).as_secs_f32(),
+            None => 0.0,
+        };
+        self.last_frame = Some(timestamp);
+
+        // Bands added or removed in the GUI start from silence
+        let num_bands = config.energy_bands.len().min(MAX_SHADER_BANDS);
+        bands.energies.resize(num_bands, 0.0);
+        for (band, envelope) in config.energy_bands.iter().zip(bands.energies.iter_mut()) {
+            let energy = band_energy(buckets, band, config.frequency_min, config.frequency_max);
+            let time_ms = if energy > *envelope {
+                band.attack_ms
+            } else {
+                band.release_ms
+            };
+            *envelope += (energy - *envelope) * smoothing_coefficient(time_ms, elapsed_seconds);
+        }
+
+        let frame_peak = bands.energies.iter().copied().fold(0.0, f32::max);
+        if frame_peak > self.peak {
+            self.peak = frame_peak;
+        } else {
+            self.peak +=
+                (frame_peak - self.peak) * smoothing_coefficient(PEAK_RELEASE_MS, elapsed_seconds);
+        }
+
+        bands.levels.clear();
+        bands.levels.extend(bands.energies.iter().map(|&energy| {
+            if self.peak <= 0.0 {
+                return 0.0;
+            }
+            (energy / self.peak).sqrt().clamp(0.0, 1.0)
+        }));
+    }
+}
+
+// Power of the buckets whose center lies inside the band
+fn band_energy(buckets: &[f32], band: &EnergyBand, frequency_min: f32, frequency_max: f32) -> f32 {
+    buckets
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| {
+            let frequency = bucket_center_frequency(i, buckets.len(), frequency_min, frequency_max);
+            frequency >= band.frequency_min && frequency < band.frequency_max
+        })
+        .map(|(_, magnitude)| magnitude * magnitude)
+        .sum()
+}
+
+pub fn band_energy_system(
+    spectrum_frames: Res<SpectrumFrames>,
+    mut tracker: ResMut<BandEnergyTracker>,
+    mut bands: ResMut<BandEnergies>,
+    config: Res<CfgResource>,
+) {
+    for frame in spectrum_frames.iter() {
+        tracker.process(&frame.mono, frame.timestamp, &config.0, &mut bands);
+    }
+}
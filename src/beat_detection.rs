@@ -0,0 +1,206 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::audio_processing::{bucket_center_frequency, SpectrumFrames};
+use crate::CfgResource;
+
+// How far back the adaptive threshold looks when computing mean and deviation
+const FLUX_HISTORY: Duration = Duration::from_millis(1500);
+// Minimum number of past frames before any onset is reported
+const MIN_HISTORY_FRAMES: usize = 8;
+// Keeps the threshold above zero during silence
+const MIN_FLUX: f32 = 1e-3;
+
+const LOW_BAND_MAX_HZ: f32 = 250.0;
+const MID_BAND_MAX_HZ: f32 = 4000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OnsetBand {
+    Low,
+    Mid,
+    High,
+}
+
+impl OnsetBand {
+    const ALL: [OnsetBand; 3] = [OnsetBand::Low, OnsetBand::Mid, OnsetBand::High];
+
+    fn from_frequency(frequency: f32) -> Self {
+        if frequency < LOW_BAND_MAX_HZ {
+            OnsetBand::Low
+        } else if frequency < MID_BAND_MAX_HZ {
+            OnsetBand::Mid
+        } else {
+            OnsetBand::High
+        }
+    }
+}
+
+/// Sent whenever the spectral flux of a band rises above its adaptive threshold.
+/// `strength` is the ratio of the flux to the threshold and is always above 1.0.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct OnsetEvent {
+    pub strength: f32,
+    pub band: OnsetBand,
+    pub timestamp: Instant,
+}
+
+/// Sent for full-spectrum onsets that are at least `beat_min_interval_ms` apart.
+/// `band` is the band that contributed the most to the onset.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BeatEvent {
+    pub strength: f32,
+    pub band: OnsetBand,
+    pub timestamp: Instant,
+}
+
+#[derive(Default)]
+struct FluxHistory {
+    values: VecDeque<(Instant, f32)>,
+    above_threshold: bool,
+    last_ratio: f32,
+}
+
+impl FluxHistory {
+    // Records a new flux value and returns its strength if it crossed the threshold
+    fn update(&mut self, timestamp: Instant, flux: f32, sensitivity: f32) -> Option<f32> {
+        while let Some(&(oldest, _)) = self.values.front() {
+            if timestamp.saturating_duration_since(oldest) > FLUX_HISTORY {
+                self.values.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let count = self.values.len() as f32;
+        let mean = self.values.iter().map(|&(_, value)| value).sum::<f32>() / count.max(1.0);
+        let variance = self
+            .values
+            .iter()
+            .map(|&(_, value)| (value - mean).powi(2))
+            .sum::<f32>()
+            / count.max(1.0);
+        let threshold = mean + sensitivity * variance.sqrt() + MIN_FLUX;
+
+        let is_above = flux > threshold;
+        let crossed = is_above && !self.above_threshold;
+        self.above_threshold = is_above;
+        self.last_ratio = flux / threshold;
+        self.values.push_back((timestamp, flux));
+
+        if crossed && self.values.len() > MIN_HISTORY_FRAMES {
+            Some(self.last_ratio)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct OnsetDetector {
+    previous_buckets: Vec<f32>,
+    band_histories: [FluxHistory; 3],
+    full_history: FluxHistory,
+    last_beat: Option<Instant>,
+}
+
+impl OnsetDetector {
+    fn process(
+        &mut self,
+        buckets: &[f32],
+        frequency_min: f32,
+        frequency_max: f32,
+    ) -> Option<([f32; 3], f32)> {
+        if self.previous_buckets.len() != buckets.len() {
+            self.previous_buckets = buckets.to_vec();
+            return None;
+        }
+
+        // Half-wave rectified difference of log magnitudes, averaged per band
+        let mut band_flux = [0.0; 3];
+        let mut band_counts = [0usize; 3];
+        for (i, (&current, previous)) in buckets
+            .iter()
+            .zip(self.previous_buckets.iter_mut())
+            .enumerate()
+        {
+            let frequency = bucket_center_frequency(i, buckets.len(), frequency_min, frequency_max);
+            let band = OnsetBand::from_frequency(frequency) as usize;
+            band_flux[band] += (current.ln_1p() - previous.ln_1p()).max(0.0);
+            band_counts[band] += 1;
+            *previous = current;
+        }
+
+        let full_flux = band_flux.iter().sum::<f32>() / buckets.len().max(1) as f32;
+        for (flux, &count) in band_flux.iter_mut().zip(band_counts.iter()) {
+            *flux /= count.max(1) as f32;
+        }
+
+        Some((band_flux, full_flux))
+    }
+}
+
+pub fn onset_detection_system(
+    spectrum_frames: Res<SpectrumFrames>,
+    mut detector: ResMut<OnsetDetector>,
+    mut onset_events: EventWriter<OnsetEvent>,
+    mut beat_events: EventWriter<BeatEvent>,
+    config: Res<CfgResource>,
+) {
+    let config = &config.0;
+
+    for frame in spectrum_frames.iter() {
+        let Some((band_flux, full_flux)) =
+            detector.process(&frame.mono, config.frequency_min, config.frequency_max)
+        else {
+            continue;
+        };
+
+        for band in OnsetBand::ALL {
+            let history = &mut detector.band_histories[band as usize];
+            if let Some(strength) = history.update(
+                frame.timestamp,
+                band_flux[band as usize],
+                config.onset_sensitivity,
+            ) {
+                onset_events.send(OnsetEvent {
+                    strength,
+                    band,
+                    timestamp: frame.timestamp,
+                });
+            }
+        }
+
+        let Some(strength) =
+            detector
+                .full_history
+                .update(frame.timestamp, full_flux, config.onset_sensitivity)
+        else {
+            continue;
+        };
+
+        let min_interval = Duration::from_secs_f32(config.beat_min_interval_ms.max(0.0) / 1000.0);
+        let beat_allowed = match detector.last_beat {
+            Some(last) => frame.timestamp.saturating_duration_since(last) >= min_interval,
+            None => true,
+        };
+
+        if beat_allowed {
+            let band = OnsetBand::ALL
+                .into_iter()
+                .max_by(|a, b| {
+                    let ratio_a = detector.band_histories[*a as usize].last_ratio;
+                    let ratio_b = detector.band_histories[*b as usize].last_ratio;
+                    ratio_a.total_cmp(&ratio_b)
+                })
+                .unwrap_or(OnsetBand::Low);
+
+            detector.last_beat = Some(frame.timestamp);
+            beat_events.send(BeatEvent {
+                strength,
+                band,
+                timestamp: frame.timestamp,
+            });
+        }
+    }
+}
@@ -1,6 +1,150 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AnalyzerMode {
+    #[default]
+    Fft,
+    ConstantQ,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TwoChannelMode {
+    LeftRight,
+    MidSide,
+    HarmonicPercussive,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelSource {
+    Left,
+    Right,
+    Mono,
+    Mid,
+    Side,
+    Max,
+}
+
+impl ChannelSource {
+    pub const ALL: [ChannelSource; 6] = [
+        ChannelSource::Left,
+        ChannelSource::Right,
+        ChannelSource::Mono,
+        ChannelSource::Mid,
+        ChannelSource::Side,
+        ChannelSource::Max,
+    ];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Free,
+    RisingEdge,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum SpectrogramOrientation {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum VectorscopeMode {
+    LeftRight,
+    MidSide,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum WeightingCurve {
+    #[default]
+    Flat,
+    A,
+    C,
+    K,
+    Itu468,
+}
+
+impl WeightingCurve {
+    pub const ALL: [WeightingCurve; 5] = [
+        WeightingCurve::Flat,
+        WeightingCurve::A,
+        WeightingCurve::C,
+        WeightingCurve::K,
+        WeightingCurve::Itu468,
+    ];
+}
+
+/// Where the spectral tilt comes from.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TiltPreset {
+    Off,
+    /// Derived from the analyzer so pink noise shows flat
+    PinkNoiseFlat,
+    /// The configured `weighting_tilt`
+    #[default]
+    Custom,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseFloorMode {
+    #[default]
+    Subtract,
+    Gate,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum StageKind {
+    Window,
+    Fft,
+    Bucketize,
+    Weighting,
+    Smoothing,
+    NoiseFloor,
+    Animate,
+    Normalize,
+}
+
+impl StageKind {
+    pub const ALL: [StageKind; 8] = [
+        StageKind::Window,
+        StageKind::Fft,
+        StageKind::Bucketize,
+        StageKind::Weighting,
+        StageKind::Smoothing,
+        StageKind::NoiseFloor,
+        StageKind::Animate,
+        StageKind::Normalize,
+    ];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EnergyBand {
+    pub name: String,
+    pub frequency_min: f32,
+    pub frequency_max: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+}
+
+impl EnergyBand {
+    fn new(
+        name: &str,
+        frequency_min: f32,
+        frequency_max: f32,
+        attack_ms: f32,
+        release_ms: f32,
+    ) -> Self {
+        EnergyBand {
+            name: name.to_string(),
+            frequency_min,
+            frequency_max,
+            attack_ms,
+            release_ms,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct MyConfig {
     pub mic_mode: bool,
     pub host: Option<String>,
@@ -8,10 +152,45 @@ pub struct MyConfig {
     pub sample_rate: i32,
     pub frequency_min: f32,
     pub frequency_max: f32,
+    pub num_buckets: usize,
+    pub analyzer: AnalyzerMode,
+    pub fft_size: usize,
+    pub pipeline: Vec<StageKind>,
+    pub two_channel_mode: TwoChannelMode,
+    pub bar_channel: ChannelSource,
+    pub polygon_channel: ChannelSource,
     pub smoothing: u32,
     pub smoothing_size: u32,
-    pub interpolation_factor: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
     pub gate_threshold: f32,
+    pub noise_floor_mode: NoiseFloorMode,
+    pub gate_hold_ms: f32,
+    pub onset_sensitivity: f32,
+    pub beat_min_interval_ms: f32,
+    pub show_meters: bool,
+    pub waveform_window_ms: f32,
+    pub waveform_points: usize,
+    pub trigger_mode: TriggerMode,
+    pub trigger_level: f32,
+    pub scope_line_width: f32,
+    pub scope_glow: f32,
+    pub spectrogram_history: usize,
+    pub spectrogram_orientation: SpectrogramOrientation,
+    pub vectorscope_mode: VectorscopeMode,
+    pub vectorscope_persistence_ms: f32,
+    pub weighting: WeightingCurve,
+    pub weighting_tilt: f32,
+    pub tilt_preset: TiltPreset,
+    pub multi_resolution: bool,
+    pub long_fft_size: usize,
+    pub short_fft_size: usize,
+    pub crossover_min: f32,
+    pub crossover_max: f32,
+    pub hpss_frames: usize,
+    pub hpss_kernel: usize,
+    pub energy_bands: Vec<EnergyBand>,
+    pub tilt_pivot_hz: f32,
 }
 impl ::std::default::Default for MyConfig {
     fn default() -> Self {
@@ -22,10 +201,52 @@ impl ::std::default::Default for MyConfig {
             sample_rate: 96000,
             frequency_min: 20.,
             frequency_max: 20_000.,
+            num_buckets: 64,
+            analyzer: AnalyzerMode::Fft,
+            fft_size: 2048,
+            pipeline: StageKind::ALL.to_vec(),
+            two_channel_mode: TwoChannelMode::LeftRight,
+            bar_channel: ChannelSource::Mono,
+            polygon_channel: ChannelSource::Mono,
             smoothing: 2,
             smoothing_size: 4,
-            interpolation_factor: 0.3,
+            attack_ms: 25.,
+            release_ms: 250.,
             gate_threshold: 0.01,
+            noise_floor_mode: NoiseFloorMode::Subtract,
+            gate_hold_ms: 150.,
+            onset_sensitivity: 1.5,
+            beat_min_interval_ms: 250.,
+            show_meters: false,
+            waveform_window_ms: 20.,
+            waveform_points: 1024,
+            trigger_mode: TriggerMode::RisingEdge,
+            trigger_level: 0.,
+            scope_line_width: 2.,
+            scope_glow: 0.5,
+            spectrogram_history: 512,
+            spectrogram_orientation: SpectrogramOrientation::Horizontal,
+            vectorscope_mode: VectorscopeMode::MidSide,
+            vectorscope_persistence_ms: 150.,
+            weighting: WeightingCurve::Flat,
+            weighting_tilt: 0.,
+            tilt_preset: TiltPreset::Custom,
+            multi_resolution: false,
+            long_fft_size: 8192,
+            short_fft_size: 1024,
+            crossover_min: 150.,
+            crossover_max: 400.,
+            hpss_frames: 17,
+            hpss_kernel: 17,
+            energy_bands: vec![
+                EnergyBand::new("Sub", 20., 60., 20., 400.),
+                EnergyBand::new("Bass", 60., 250., 15., 300.),
+                EnergyBand::new("Low-Mid", 250., 500., 10., 250.),
+                EnergyBand::new("Mid", 500., 2_000., 10., 200.),
+                EnergyBand::new("High-Mid", 2_000., 4_000., 5., 150.),
+                EnergyBand::new("Treble", 4_000., 20_000., 5., 120.),
+            ],
+            tilt_pivot_hz: 1_000.,
         }
     }
 }
@@ -0,0 +1,227 @@
+use bevy::prelude::*;
+use std::time::Instant;
+
+use crate::audio_processing::{smoothing_coefficient, SpectrumFrames};
+use crate::fft::{FftAnalyzer, HannWindow};
+use crate::materials::ChromaMaterial;
+use crate::multi_resolution::SampleHistory;
+use crate::tempo::TempoEstimate;
+use crate::visualization::VisualizationType;
+
+pub const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+// Bins outside this range carry little pitch information
+const MIN_PITCH_HZ: f32 = 55.0;
+const MAX_PITCH_HZ: f32 = 5000.0;
+// Long enough for bins a semitone apart from about 100 Hz up at 48 kHz
+const CHROMA_FFT_SIZE: usize = 8192;
+// Relative width of a semitone
+const SEMITONE_WIDTH: f32 = 0.059_463_1;
+
+// Time constants of the displayed chroma and of the profile the key is read from
+const CHROMA_TIME_MS: f32 = 120.0;
+const KEY_TIME_MS: f32 = 8000.0;
+// Below this correlation the profile is too flat to name a key
+const MIN_KEY_CORRELATION: f32 = 0.3;
+
+// Krumhansl-Kessler probe tone profiles, starting at the tonic
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMode {
+    Major,
+    Minor,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MusicalKey {
+    /// Pitch class of the tonic, 0 is C
+    pub tonic: usize,
+    pub mode: KeyMode,
+    /// Correlation with the matching key profile, 0.0 to 1.0
+    pub confidence: f32,
+}
+
+impl MusicalKey {
+    pub fn name(&self) -> String {
+        let mode = match self.mode {
+            KeyMode::Major => "major",
+            KeyMode::Minor => "minor",
+        };
+        format!("{} {}", NOTE_NAMES[self.tonic], mode)
+    }
+}
+
+/// Pitch-class energy starting at C, scaled so the strongest class is 1.0,
+/// together with the key estimated over the last several seconds.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct Chroma {
+    pub bins: [f32; 12],
+    pub key: Option<MusicalKey>,
+}
+
+#[derive(Resource, Default)]
+pub struct ChromaTracker {
+    profile: [f32; 12],
+    last_frame: Option<Instant>,
+}
+
+/// Chromagram of the mono mix, folded from the bins of a long FFT of its own
+/// on the DSP worker. The display buckets are far too wide and too processed
+/// to tell pitch classes apart.
+#[derive(Default)]
+pub struct ChromaAnalyzer {
+    history: SampleHistory,
+    window: HannWindow,
+    fft: FftAnalyzer,
+    block: Vec<f32>,
+    magnitudes: Vec<f32>,
+}
+
+impl ChromaAnalyzer {
+    pub fn push(&mut self, left: &[f32], right: &[f32], sample_rate: u32) {
+        self.block.clear();
+        self.block.extend(
+            left.iter()
+                .zip(right.iter())
+                .map(|(left, right)| (left + right) * 0.5),
+        );
+        self.history.push(&self.block, sample_rate, CHROMA_FFT_SIZE);
+    }
+
+    /// Folds every bin of the newest block onto the nearest of the twelve
+    /// pitch classes, starting at C. Returns `None` before any audio arrived.
+    pub fn analyze(&mut self, sample_rate: u32) -> Option<[f32; 12]> {
+        self.block.clear();
+        self.block
+            .extend_from_slice(self.history.latest(CHROMA_FFT_SIZE));
+        self.window.apply(&mut self.block);
+        let fft_len = self.fft.magnitudes(&self.block, &mut self.magnitudes)?;
+        let bin_hz = sample_rate as f32 / fft_len as f32;
+
+        // Below this the bins are wider than a semitone
+        let min_frequency = (bin_hz / SEMITONE_WIDTH).max(MIN_PITCH_HZ);
+        let mut chroma = [0.0; 12];
+        for (bin, &magnitude) in self.magnitudes.iter().enumerate().skip(1) {
+            let frequency = bin as f32 * bin_hz;
+            if frequency > MAX_PITCH_HZ {
+                break;
+            }
+            if frequency < min_frequency {
+                continue;
+            }
+
+            // Semitones above C, A4 being 440 Hz
+            let pitch = (12.0 * (frequency / 440.0).log2() + 9.0).round();
+            chroma[pitch.rem_euclid(12.0) as usize % 12] += magnitude;
+        }
+        Some(chroma)
+    }
+}
+
+fn correlation(a: &[f32; 12], b: impl Fn(usize) -> f32) -> f32 {
+    let mean_a = a.iter().sum::<f32>() / 12.0;
+    let mean_b = (0..12).map(&b).sum::<f32>() / 12.0;
+    let mut covariance = 0.0;
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    for (i, &value) in a.iter().enumerate() {
+        let da = value - mean_a;
+        let db = b(i) - mean_b;
+        covariance += da * db;
+        variance_a += da * da;
+        variance_b += db * db;
+    }
+    let denominator = (variance_a * variance_b).sqrt();
+    if denominator <= f32::EPSILON {
+        0.0
+    } else {
+        covariance / denominator
+    }
+}
+
+// Compares the profile against all 24 rotated key profiles
+fn estimate_key(profile: &[f32; 12]) -> Option<MusicalKey> {
+    let mut best: Option<MusicalKey> = None;
+    for (mode, key_profile) in [
+        (KeyMode::Major, &MAJOR_PROFILE),
+        (KeyMode::Minor, &MINOR_PROFILE),
+    ] {
+        for tonic in 0..12 {
+            let score = correlation(profile, |i| key_profile[(i + 12 - tonic) % 12]);
+            let is_better = match best {
+                Some(key) => score > key.confidence,
+                None => true,
+            };
+            if is_better {
+                best = Some(MusicalKey {
+                    tonic,
+                    mode,
+                    confidence: score,
+                });
+            }
+        }
+    }
+    best.filter(|key| key.confidence >= MIN_KEY_CORRELATION)
+}
+
+pub fn chroma_system(
+    spectrum_frames: Res<SpectrumFrames>,
+    mut tracker: ResMut<ChromaTracker>,
+    mut chroma: ResMut<Chroma>,
+) {
+    for frame in spectrum_frames.iter() {
+        let elapsed_seconds = match tracker.last_frame {
+            Some(last) => frame
+                .timestamp
+                .saturating_duration_since(last)
+                .as_secs_f32(),
+            None => 0.0,
+        };
+        tracker.last_frame = Some(frame.timestamp);
+
+        let frame_chroma = frame.chroma;
+        let peak = frame_chroma.iter().copied().fold(0.0, f32::max);
+        let display_coefficient = smoothing_coefficient(CHROMA_TIME_MS, elapsed_seconds);
+        let key_coefficient = smoothing_coefficient(KEY_TIME_MS, elapsed_seconds);
+        for (i, &value) in frame_chroma.iter().enumerate() {
+            let normalized = if peak > 0.0 { value / peak } else { 0.0 };
+            chroma.bins[i] += (normalized - chroma.bins[i]) * display_coefficient;
+            tracker.profile[i] += (value - tracker.profile[i]) * key_coefficient;
+        }
+    }
+
+    chroma.key = estimate_key(&tracker.profile);
+}
+
+pub fn chroma_material_system(
+    chroma: Res<Chroma>,
+    tempo: Res<TempoEstimate>,
+    visualization_type: Res<VisualizationType>,
+    mut chroma_material: ResMut<Assets<ChromaMaterial>>,
+) {
+    if !matches!(*visualization_type, VisualizationType::Chroma) {
+        return;
+    }
+
+    for (_, material) in chroma_material.iter_mut() {
+        for (i, value) in chroma.bins.iter().enumerate() {
+            material.chroma[i / 4][i % 4] = *value;
+        }
+        match chroma.key {
+            Some(key) => {
+                material.key_tonic = key.tonic as i32;
+                material.key_minor = if key.mode == KeyMode::Minor { 1 } else { 0 };
+            }
+            None => material.key_tonic = -1,
+        }
+        material.beat_phase = tempo.beat_phase;
+    }
+}
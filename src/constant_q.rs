@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use crate::audio_processing::bucket_center_frequency;
+
+// Bounds on the quality factor derived from the bucket spacing; the upper bound
+// keeps the longest analysis window under a second at 20 Hz
+const MIN_Q: f32 = 2.0;
+const MAX_Q: f32 = 17.0;
+// Every bin is analyzed at the lowest sample rate that still keeps it below a
+// quarter of that rate, which bounds every window to at most 8 * Q samples
+const MAX_LEVELS: usize = 12;
+const MAX_HISTORY: usize = 1 << 16;
+const MIN_WINDOW: usize = 8;
+const DECIMATION_TAPS: usize = 15;
+// Output is scaled to what a Hann-windowed FFT of this size reports for the same
+// sinusoid, so thresholds tuned for the FFT analyzer behave alike in both modes
+const REFERENCE_FFT_SIZE: f32 = 1024.0;
+
+struct Kernel {
+    level: usize,
+    real: Vec<f32>,
+    imag: Vec<f32>,
+    scale: f32,
+}
+
+/// Constant-Q analyzer producing one logarithmically spaced bin per bucket.
+/// Low bins are evaluated on decimated copies of the input, kept up to date
+/// as samples arrive, so bass gets long windows while the treble keeps a
+/// short one.
+pub struct ConstantQ {
+    sample_rate: u32,
+    frequency_min: f32,
+    frequency_max: f32,
+    num_bins: usize,
+    kernels: Vec<Kernel>,
+    decimation_filter: [f32; DECIMATION_TAPS],
+    // Rolling history at each decimation level, newest last, and whether the
+    // next sample of a level also produces one for the level below
+    levels: Vec<VecDeque<f32>>,
+    decimate_next: Vec<bool>,
+}
+
+impl ConstantQ {
+    pub fn new(sample_rate: u32, frequency_min: f32, frequency_max: f32, num_bins: usize) -> Self {
+        let ratio = (frequency_max / frequency_min).powf(1.0 / (num_bins as f32 - 1.0).max(1.0));
+        let q = (1.0 / (ratio - 1.0)).clamp(MIN_Q, MAX_Q);
+
+        let mut kernels = Vec::with_capacity(num_bins);
+        let mut needed = [0usize; MAX_LEVELS];
+
+        for i in 0..num_bins {
+            let frequency = bucket_center_frequency(i, num_bins, frequency_min, frequency_max);
+            let level = ((sample_rate as f32 / (4.0 * frequency))
+                .log2()
+                .floor()
+                .max(0.0) as usize)
+                .min(MAX_LEVELS - 1);
+            let level_rate = sample_rate as f32 / (1 << level) as f32;
+            let window_len = ((q * level_rate / frequency).ceil() as usize)
+                .clamp(MIN_WINDOW, MAX_HISTORY >> level);
+
+            let mut real = Vec::with_capacity(window_len);
+            let mut imag = Vec::with_capacity(window_len);
+            let mut window_sum = 0.0;
+            for n in 0..window_len {
+                let window = 0.5 - 0.5 * (2.0 * PI * n as f32 / window_len as f32).cos();
+                let phase = 2.0 * PI * frequency * n as f32 / level_rate;
+                real.push(window * phase.cos());
+                imag.push(-window * phase.sin());
+                window_sum += window;
+            }
+
+            needed[level] = needed[level].max(window_len);
+            kernels.push(Kernel {
+                level,
+                real,
+                imag,
+                scale: REFERENCE_FFT_SIZE / (2.0 * window_sum),
+            });
+        }
+
+        let num_levels = kernels
+            .iter()
+            .map(|kernel| kernel.level + 1)
+            .max()
+            .unwrap_or(1);
+
+        ConstantQ {
+            sample_rate,
+            frequency_min,
+            frequency_max,
+            num_bins,
+            kernels,
+            decimation_filter: half_band_filter(),
+            // Each level holds its own longest window and at least the filter
+            // input of the next level down
+            levels: needed[..num_levels]
+                .iter()
+                .map(|&len| VecDeque::from(vec![0.0; len.max(DECIMATION_TAPS)]))
+                .collect(),
+            decimate_next: vec![false; num_levels],
+        }
+    }
+
+    pub fn matches(
+        &self,
+        sample_rate: u32,
+        frequency_min: f32,
+        frequency_max: f32,
+        num_bins: usize,
+    ) -> bool {
+        self.sample_rate == sample_rate
+            && self.frequency_min == frequency_min
+            && self.frequency_max == frequency_max
+            && self.num_bins == num_bins
+    }
+
+    /// Appends new samples to every decimation level. The filtering costs
+    /// less than one filter evaluation per input sample on average, however
+    /// long the windows are.
+    pub fn push_samples(&mut self, samples: &[f32]) {
+        let last_level = self.levels.len() - 1;
+        for &sample in samples {
+            let mut value = sample;
+            for level in 0..=last_level {
+                let history = &mut self.levels[level];
+                history.pop_front();
+                history.push_back(value);
+
+                // Every other sample continues filtered to the next level
+                let decimate = self.decimate_next[level];
+                self.decimate_next[level] = !decimate;
+                if !decimate || level == last_level {
+                    break;
+                }
+                value = self
+                    .decimation_filter
+                    .iter()
+                    .zip(history.range(history.len() - DECIMATION_TAPS..))
+                    .map(|(tap, value)| tap * value)
+                    .sum();
+            }
+        }
+    }
+
+    /// Writes the magnitude of every bin into `bins`.
+    pub fn analyze(&self, bins: &mut [f32]) {
+        for (bin, kernel) in bins.iter_mut().zip(&self.kernels) {
+            let samples = &self.levels[kernel.level];
+            let window = samples.range(samples.len() - kernel.real.len()..);
+            let mut real = 0.0;
+            let mut imag = 0.0;
+            for ((value, kernel_real), kernel_imag) in window.zip(&kernel.real).zip(&kernel.imag) {
+                real += value * kernel_real;
+                imag += value * kernel_imag;
+            }
+            *bin = (real * real + imag * imag).sqrt() * kernel.scale;
+        }
+    }
+}
+
+// Blackman-windowed sinc low-pass at a quarter of the sample rate, applied
+// before dropping every other sample
+fn half_band_filter() -> [f32; DECIMATION_TAPS] {
+    let mut taps = [0.0; DECIMATION_TAPS];
+    let center = (DECIMATION_TAPS - 1) as f32 / 2.0;
+    for (i, tap) in taps.iter_mut().enumerate() {
+        let x = i as f32 - center;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (0.5 * PI * x).sin() / (0.5 * PI * x)
+        };
+        let phase = 2.0 * PI * i as f32 / (DECIMATION_TAPS - 1) as f32;
+        let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+        *tap = sinc * window;
+    }
+    let sum: f32 = taps.iter().sum();
+    for tap in taps.iter_mut() {
+        *tap /= sum;
+    }
+    taps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const NUM_BINS: usize = 64;
+    const BLOCK_LEN: usize = 480;
+
+    // Analyzes two seconds of a unit sine at the center of `bin`, pushed in
+    // blocks like the capture delivers them
+    fn analyze_sine(bin: usize) -> Vec<f32> {
+        let mut analyzer = ConstantQ::new(SAMPLE_RATE, 20.0, 20_000.0, NUM_BINS);
+        let frequency = bucket_center_frequency(bin, NUM_BINS, 20.0, 20_000.0);
+        let samples: Vec<f32> = (0..2 * SAMPLE_RATE as usize)
+            .map(|n| (2.0 * PI * frequency * n as f32 / SAMPLE_RATE as f32).sin())
+            .collect();
+        for block in samples.chunks(BLOCK_LEN) {
+            analyzer.push_samples(block);
+        }
+        let mut bins = vec![0.0; NUM_BINS];
+        analyzer.analyze(&mut bins);
+        bins
+    }
+
+    #[test]
+    fn sine_peaks_at_its_bin_on_every_level() {
+        // A unit sine reads about a quarter of the reference FFT size
+        let expected = REFERENCE_FFT_SIZE / 4.0;
+        for bin in [2, 12, 30, 50, 60] {
+            let bins = analyze_sine(bin);
+            let peak = (0..NUM_BINS)
+                .max_by(|&a, &b| bins[a].total_cmp(&bins[b]))
+                .unwrap();
+            assert_eq!(peak, bin, "bins {:?}", bins);
+            assert!(
+                (bins[bin] / expected - 1.0).abs() < 0.2,
+                "bin {} reads {} instead of {}",
+                bin,
+                bins[bin],
+                expected
+            );
+        }
+    }
+}
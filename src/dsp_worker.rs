@@ -0,0 +1,412 @@
+use bevy::prelude::*;
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::audio_capture::AudioProcessedEvent;
+use crate::audio_processing::analysis_channels;
+use crate::cfg::{AnalyzerMode, TwoChannelMode};
+use crate::chroma::ChromaAnalyzer;
+use crate::hpss::HarmonicPercussiveSeparator;
+use crate::multi_resolution::{block_lengths, SampleHistory};
+use crate::pipeline::{Pipeline, StageContext, StageData, StagePhase};
+use crate::{CfgResource, MyConfig};
+
+// How long the worker waits for audio before checking for new settings
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Analysis parameters of the worker, sent whenever the GUI or the
+/// visualization changes them.
+#[derive(Clone, PartialEq)]
+pub struct AnalysisSettings {
+    pub config: MyConfig,
+    pub channel_mode: TwoChannelMode,
+}
+
+enum WorkerMessage {
+    Settings(Box<AnalysisSettings>),
+    // The capture thread is restarted with a new channel on every visualization change
+    Capture(Arc<Mutex<Receiver<AudioProcessedEvent>>>),
+}
+
+/// Buckets of the newest buffer of a batch, together with every captured
+/// buffer of the batch so the level meters still see all of the audio.
+/// Buckets are `None` when the analysis failed. With harmonic/percussive
+/// separation `left` holds the harmonic and `right` the percussive part of the
+/// mono mix.
+/// `mono` is the mono mix before the bucket stages, for the analyses that
+/// must not depend on the display settings, and `chroma` its pitch classes.
+pub struct AnalyzedFrame {
+    pub left: Option<Vec<f32>>,
+    pub right: Option<Vec<f32>>,
+    pub mono: Option<Vec<f32>>,
+    pub chroma: [f32; 12],
+    pub timestamp: Instant,
+    pub audio: Vec<AudioProcessedEvent>,
+}
+
+/// Handle to the thread running the analysis stages of the pipeline, from
+/// the window up to the bucket stages, so none of it runs in `Update`.
+#[derive(Resource)]
+pub struct DspWorker {
+    messages: Mutex<Sender<WorkerMessage>>,
+    frames: Mutex<Receiver<AnalyzedFrame>>,
+    // Bucket buffers and emptied batches handed back for the worker to fill again
+    spare_buffers: Mutex<Sender<Vec<f32>>>,
+    spare_batches: Mutex<Sender<Vec<AudioProcessedEvent>>>,
+    // Never joined; the worker exits by itself once the resource is dropped
+    _thread_handle: JoinHandle<()>,
+}
+
+impl FromWorld for DspWorker {
+    fn from_world(world: &mut World) -> Self {
+        let config = world.get_resource::<CfgResource>().unwrap().0.clone();
+        DspWorker::spawn(AnalysisSettings {
+            config,
+            channel_mode: TwoChannelMode::LeftRight,
+        })
+    }
+}
+
+impl DspWorker {
+    fn spawn(settings: AnalysisSettings) -> Self {
+        let (message_sender, message_receiver) = channel();
+        let (frame_sender, frame_receiver) = channel();
+        let (spare_sender, spare_receiver) = channel();
+        let (batch_sender, batch_receiver) = channel();
+
+        let thread_handle = thread::spawn(move || {
+            run_worker(
+                settings,
+                message_receiver,
+                frame_sender,
+                spare_receiver,
+                batch_receiver,
+            );
+        });
+
+        DspWorker {
+            messages: Mutex::new(message_sender),
+            frames: Mutex::new(frame_receiver),
+            spare_buffers: Mutex::new(spare_sender),
+            spare_batches: Mutex::new(batch_sender),
+            _thread_handle: thread_handle,
+        }
+    }
+
+    pub fn configure(&self, settings: AnalysisSettings) {
+        self.send(WorkerMessage::Settings(Box::new(settings)));
+    }
+
+    pub fn set_capture(&self, receiver: Arc<Mutex<Receiver<AudioProcessedEvent>>>) {
+        self.send(WorkerMessage::Capture(receiver));
+    }
+
+    fn send(&self, message: WorkerMessage) {
+        if self.messages.lock().unwrap().send(message).is_err() {
+            eprintln!("The DSP worker has stopped, the spectrum will no longer update.");
+        }
+    }
+
+    /// Appends every frame finished since the last call to `frames`, oldest
+    /// first.
+    pub fn receive_frames(&self, frames: &mut Vec<AnalyzedFrame>) {
+        frames.extend(self.frames.lock().unwrap().try_iter());
+    }
+
+    /// Returns a bucket buffer that is no longer displayed, so the worker can
+    /// reuse its allocation for a later frame.
+    pub fn recycle(&self, buffer: Vec<f32>) {
+        // A stopped worker has no use for it
+        let _ = self.spare_buffers.lock().unwrap().send(buffer);
+    }
+
+    /// Returns the emptied audio batch of a frame for the same purpose.
+    pub fn recycle_batch(&self, batch: Vec<AudioProcessedEvent>) {
+        let _ = self.spare_batches.lock().unwrap().send(batch);
+    }
+}
+
+// Recent samples of one analyzed channel and the stages turning them into
+// buckets
+struct ChannelAnalyzer {
+    history: SampleHistory,
+    data: StageData,
+    pipeline: Pipeline,
+}
+
+impl ChannelAnalyzer {
+    fn new(last_phase: StagePhase) -> Self {
+        ChannelAnalyzer {
+            history: SampleHistory::default(),
+            data: StageData::default(),
+            pipeline: Pipeline::new(StagePhase::Samples..=last_phase),
+        }
+    }
+
+    fn push(&mut self, samples: &[f32], sample_rate: u32) {
+        self.data.input.extend_from_slice(samples);
+        self.data.sample_rate = sample_rate;
+    }
+
+    fn push_mono(&mut self, left: &[f32], right: &[f32], sample_rate: u32) {
+        self.data.input.extend(
+            left.iter()
+                .zip(right.iter())
+                .map(|(left, right)| (left + right) * 0.5),
+        );
+        self.data.sample_rate = sample_rate;
+    }
+
+    // Cuts the newest blocks for the transform out of the history and drops
+    // the results of the previous frame
+    fn start_frame(&mut self, block_lengths: Option<(usize, Option<usize>)>) {
+        self.data.buckets.clear();
+        self.data.block.clear();
+        self.data.short_block.clear();
+
+        let Some((length, short_length)) = block_lengths else {
+            // Audio from before the FFT is turned back on must not be mixed in
+            self.history.clear();
+            return;
+        };
+        self.history
+            .push(&self.data.input, self.data.sample_rate, length);
+        let samples = self.history.latest(length);
+        self.data.block.fill(samples);
+        if let Some(short_length) = short_length {
+            self.data
+                .short_block
+                .fill(&samples[samples.len().saturating_sub(short_length)..]);
+        }
+    }
+
+    // Hands out the finished buckets, replacing them with a spare buffer
+    fn take_buckets(&mut self, spare_buffers: &Receiver<Vec<f32>>) -> Option<Vec<f32>> {
+        if self.data.buckets.is_empty() {
+            return None;
+        }
+        let spare = spare_buffers.try_recv().unwrap_or_default();
+        Some(mem::replace(&mut self.data.buckets, spare))
+    }
+}
+
+// State carried from one analyzed batch to the next. Once the plans and
+// buffers match the settings, analyzing a frame allocates nothing.
+struct FrameAnalyzer {
+    left: ChannelAnalyzer,
+    right: ChannelAnalyzer,
+    // Stops at the buckets, before any stage the display settings control
+    mono: ChannelAnalyzer,
+    chroma: ChromaAnalyzer,
+    separator: HarmonicPercussiveSeparator,
+    // Lengths of the blocks transformed for the two channels and for mono
+    channel_blocks: Option<(usize, Option<usize>)>,
+    mono_blocks: Option<(usize, Option<usize>)>,
+    // Mid and side signals of the buffer being analyzed
+    mid_side: (Vec<f32>, Vec<f32>),
+    // Bucket buffers returned by the main thread
+    spare_buffers: Receiver<Vec<f32>>,
+    last_frame: Option<Instant>,
+}
+
+impl FrameAnalyzer {
+    fn new(settings: &AnalysisSettings, spare_buffers: Receiver<Vec<f32>>) -> Self {
+        let mut analyzer = FrameAnalyzer {
+            left: ChannelAnalyzer::new(StagePhase::Buckets),
+            right: ChannelAnalyzer::new(StagePhase::Buckets),
+            mono: ChannelAnalyzer::new(StagePhase::Spectrum),
+            chroma: ChromaAnalyzer::default(),
+            separator: HarmonicPercussiveSeparator::default(),
+            channel_blocks: None,
+            mono_blocks: None,
+            mid_side: (Vec::new(), Vec::new()),
+            spare_buffers,
+            last_frame: None,
+        };
+        analyzer.configure(settings);
+        analyzer
+    }
+
+    fn configure(&mut self, settings: &AnalysisSettings) {
+        let config = &settings.config;
+        // The separation needs the bins of a single plain FFT
+        let separated_config;
+        let channel_config = if settings.channel_mode == TwoChannelMode::HarmonicPercussive {
+            separated_config = MyConfig {
+                analyzer: AnalyzerMode::Fft,
+                multi_resolution: false,
+                ..config.clone()
+            };
+            &separated_config
+        } else {
+            config
+        };
+
+        self.left.pipeline.configure(channel_config);
+        self.right.pipeline.configure(channel_config);
+        self.mono.pipeline.configure(config);
+        self.channel_blocks = block_lengths(channel_config);
+        self.mono_blocks = block_lengths(config);
+    }
+
+    // Time covered by a newly analyzed frame, measured between capture timestamps
+    fn frame_seconds(&mut self, timestamp: Instant) -> f32 {
+        let elapsed_seconds = match self.last_frame {
+            Some(last) => timestamp.saturating_duration_since(last).as_secs_f32(),
+            None => 0.0,
+        };
+        self.last_frame = Some(timestamp);
+        elapsed_seconds
+    }
+
+    // Every buffer of the batch goes into the histories, but only the newest
+    // blocks are transformed so the display never lags behind the audio
+    fn analyze(
+        &mut self,
+        audio: Vec<AudioProcessedEvent>,
+        settings: &AnalysisSettings,
+    ) -> Option<AnalyzedFrame> {
+        self.left.data.input.clear();
+        self.right.data.input.clear();
+        self.mono.data.input.clear();
+        for audio_event in &audio {
+            let (left, right) =
+                analysis_channels(audio_event, settings.channel_mode, &mut self.mid_side);
+            self.left.push(left, audio_event.sample_rate);
+            self.right.push(right, audio_event.sample_rate);
+            self.mono.push_mono(
+                &audio_event.left,
+                &audio_event.right,
+                audio_event.sample_rate,
+            );
+            self.chroma.push(
+                &audio_event.left,
+                &audio_event.right,
+                audio_event.sample_rate,
+            );
+        }
+
+        let newest = audio.last()?;
+        let (timestamp, sample_rate) = (newest.timestamp, newest.sample_rate);
+        let context = StageContext {
+            elapsed_seconds: self.frame_seconds(timestamp),
+            target_age_seconds: 0.0,
+        };
+
+        self.left.start_frame(self.channel_blocks);
+        self.right.start_frame(self.channel_blocks);
+        self.mono.start_frame(self.mono_blocks);
+        self.mono.pipeline.process(&mut self.mono.data, &context);
+
+        if settings.channel_mode == TwoChannelMode::HarmonicPercussive {
+            // Both parts are separated from the spectrum of the mono mix
+            // analyzed as the left channel, then bucketized separately
+            self.left.pipeline.process_phases(
+                ..StagePhase::Spectrum,
+                &mut self.left.data,
+                &context,
+            );
+            let harmonic = &mut self.left.data.block;
+            let percussive = &mut self.right.data.block;
+            percussive.bin_hz = harmonic.bin_hz;
+            percussive.energy_gain = harmonic.energy_gain;
+            percussive.spectrum.clear();
+            if !harmonic.spectrum.is_empty() {
+                self.separator.separate(
+                    &mut harmonic.spectrum,
+                    &mut percussive.spectrum,
+                    &settings.config,
+                );
+            }
+            for channel in [&mut self.left, &mut self.right] {
+                channel.pipeline.process_phases(
+                    StagePhase::Spectrum..,
+                    &mut channel.data,
+                    &context,
+                );
+            }
+        } else {
+            for channel in [&mut self.left, &mut self.right] {
+                channel.pipeline.process(&mut channel.data, &context);
+            }
+        }
+
+        Some(AnalyzedFrame {
+            left: self.left.take_buckets(&self.spare_buffers),
+            right: self.right.take_buckets(&self.spare_buffers),
+            mono: self.mono.take_buckets(&self.spare_buffers),
+            chroma: self.chroma.analyze(sample_rate).unwrap_or_default(),
+            timestamp,
+            audio,
+        })
+    }
+}
+
+fn run_worker(
+    mut settings: AnalysisSettings,
+    messages: Receiver<WorkerMessage>,
+    frames: Sender<AnalyzedFrame>,
+    spare_buffers: Receiver<Vec<f32>>,
+    spare_batches: Receiver<Vec<AudioProcessedEvent>>,
+) {
+    let mut analyzer = FrameAnalyzer::new(&settings, spare_buffers);
+    let mut capture: Option<Arc<Mutex<Receiver<AudioProcessedEvent>>>> = None;
+
+    loop {
+        // Apply everything the main thread sent since the last batch; without
+        // a capture channel there is nothing to do but wait for one
+        let message = match &capture {
+            Some(_) => match messages.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            },
+            None => match messages.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return,
+            },
+        };
+        if let Some(message) = message {
+            match message {
+                WorkerMessage::Settings(new_settings) => {
+                    settings = *new_settings;
+                    analyzer.configure(&settings);
+                }
+                WorkerMessage::Capture(receiver) => capture = Some(receiver),
+            }
+            continue;
+        }
+
+        let Some(receiver) = &capture else {
+            continue;
+        };
+        let audio: Vec<AudioProcessedEvent> = {
+            let receiver = receiver.lock().unwrap();
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                // Batch whatever else arrived while the last frame was analyzed
+                Ok(first) => {
+                    let mut audio = spare_batches.try_recv().unwrap_or_default();
+                    audio.push(first);
+                    audio.extend(receiver.try_iter());
+                    audio
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    drop(receiver);
+                    capture = None;
+                    continue;
+                }
+            }
+        };
+
+        if let Some(frame) = analyzer.analyze(audio, &settings) {
+            if frames.send(frame).is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use realfft::num_complex::Complex;
+use realfft::{RealFftPlanner, RealToComplex};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Hann window of the length last applied, rebuilt only when that changes.
+#[derive(Default)]
+pub struct HannWindow {
+    weights: Vec<f32>,
+    // Sum of the squared weights
+    energy: f32,
+}
+
+impl HannWindow {
+    /// Multiplies the samples by the window in place.
+    pub fn apply(&mut self, samples: &mut [f32]) {
+        let len = samples.len();
+        if self.weights.len() != len {
+            let denominator = (len as f32 - 1.0).max(1.0);
+            self.weights.clear();
+            self.weights
+                .extend((0..len).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / denominator).cos()));
+            self.energy = self.weights.iter().map(|weight| weight * weight).sum();
+        }
+
+        for (sample, weight) in samples.iter_mut().zip(&self.weights) {
+            *sample *= weight;
+        }
+    }
+
+    /// Sum of the squared weights, which scales the spectrum power of noise.
+    pub fn energy(&self) -> f32 {
+        self.energy
+    }
+}
+
+/// Real FFT that keeps its plan and work buffers, so transforming blocks of a
+/// length seen before performs no heap allocations. Plans of every transform
+/// length used so far stay cached in the planner.
+pub struct FftAnalyzer {
+    planner: RealFftPlanner<f32>,
+    plan: Option<Arc<dyn RealToComplex<f32>>>,
+    input: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl Default for FftAnalyzer {
+    fn default() -> Self {
+        FftAnalyzer {
+            planner: RealFftPlanner::new(),
+            plan: None,
+            input: Vec::new(),
+            spectrum: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+}
+
+impl FftAnalyzer {
+    /// Pads the already windowed samples with zeroes to the next power of two
+    /// and writes the magnitude of every bin from DC to Nyquist into
+    /// `magnitudes`. Returns the transform length, or `None` when the samples
+    /// could not be transformed.
+    pub fn magnitudes(&mut self, samples: &[f32], magnitudes: &mut Vec<f32>) -> Option<usize> {
+        let len = samples.len();
+        if len == 0 {
+            return None;
+        }
+
+        let fft_len = len.next_power_of_two();
+        if self.plan.as_ref().map(|plan| plan.len()) != Some(fft_len) {
+            let plan = self.planner.plan_fft_forward(fft_len);
+            self.input = plan.make_input_vec();
+            self.spectrum = plan.make_output_vec();
+            self.scratch = plan.make_scratch_vec();
+            self.plan = Some(plan);
+        }
+        let plan = self.plan.as_ref()?;
+
+        self.input[..len].copy_from_slice(samples);
+        self.input[len..].fill(0.0);
+        plan.process_with_scratch(&mut self.input, &mut self.spectrum, &mut self.scratch)
+            .ok()?;
+
+        magnitudes.clear();
+        magnitudes.extend(self.spectrum.iter().map(|bin| bin.norm()));
+        Some(fft_len)
+    }
+}
+
+/// How the bins falling into one bucket are combined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinSum {
+    /// Sum of the magnitudes
+    Magnitude,
+    /// Root of the summed bin power times the given scale
+    Power(f32),
+}
+
+/// Bucket catching a frequency: the logarithmic axis between the limits is
+/// split into `num_buckets - 1` equal steps, so the last bucket only catches
+/// the upper limit itself.
+pub fn bucket_index(
+    log_frequency: f32,
+    log_min_frequency: f32,
+    log_max_frequency: f32,
+    num_buckets: usize,
+) -> usize {
+    let scale = (log_frequency - log_min_frequency) / (log_max_frequency - log_min_frequency);
+    ((scale.max(0.0) * (num_buckets as f32 - 1.0)) as usize).min(num_buckets - 1)
+}
+
+/// Lower and upper edge in Hz of the frequencies `bucket_index` assigns to a
+/// bucket. Both edges of the last bucket are the upper limit.
+pub fn bucket_edges(
+    index: usize,
+    num_buckets: usize,
+    frequency_min: f32,
+    frequency_max: f32,
+) -> (f32, f32) {
+    let log_min_frequency = frequency_min.ln();
+    let log_bucket_width =
+        (frequency_max.ln() - log_min_frequency) / (num_buckets as f32 - 1.0).max(1.0);
+    let edge = |i: usize| {
+        if i + 1 >= num_buckets {
+            frequency_max
+        } else {
+            (log_min_frequency + i as f32 * log_bucket_width).exp()
+        }
+    };
+    (edge(index), edge(index + 1))
+}
+
+/// Maps transform bins onto logarithmically spaced buckets.
+#[derive(Default)]
+pub struct Bucketizer {
+    // Buckets that received at least one bin
+    filled: Vec<bool>,
+}
+
+impl Bucketizer {
+    /// Fills `buckets` from the bin magnitudes between the frequency limits.
+    /// Returns false when no bin lies between them.
+    pub fn bucketize(
+        &mut self,
+        magnitudes: &[f32],
+        bin_hz: f32,
+        (frequency_min, frequency_max): (f32, f32),
+        sum: BinSum,
+        buckets: &mut [f32],
+    ) -> bool {
+        if buckets.is_empty() || magnitudes.len() < 2 || frequency_min >= frequency_max {
+            return false;
+        }
+
+        // The scale spans exactly the limits, so the bucket frequencies the
+        // analysis stages derive from them match the bins. The DC bin has no
+        // place on a logarithmic scale, and bins sitting exactly on a limit
+        // must not be lost to rounding.
+        let first_bin = ((frequency_min / bin_hz - 1e-3).ceil() as usize).max(1);
+        let last_bin = ((frequency_max / bin_hz + 1e-3).floor() as usize).min(magnitudes.len() - 1);
+        let log_min_frequency = frequency_min.ln();
+        let log_max_frequency = frequency_max.ln();
+        if first_bin >= last_bin {
+            return false;
+        }
+
+        let num_buckets = buckets.len();
+        buckets.fill(0.0);
+        self.filled.clear();
+        self.filled.resize(num_buckets, false);
+
+        for (bin, magnitude) in magnitudes
+            .iter()
+            .enumerate()
+            .take(last_bin + 1)
+            .skip(first_bin)
+        {
+            let bucket_index = bucket_index(
+                (bin as f32 * bin_hz).ln(),
+                log_min_frequency,
+                log_max_frequency,
+                num_buckets,
+            );
+
+            buckets[bucket_index] += match sum {
+                BinSum::Power(_) => magnitude * magnitude,
+                BinSum::Magnitude => *magnitude,
+            };
+            self.filled[bucket_index] = true;
+        }
+
+        // At low frequencies the buckets are narrower than the bin spacing and
+        // catch a bin only now and then. They take the spectrum at their center
+        // instead, scaled by the share of a bin their width covers. That is
+        // what their bins would sum to on average, so noise keeps the slope it
+        // has in the wider buckets.
+        let log_bucket_width =
+            (log_max_frequency - log_min_frequency) / (num_buckets as f32 - 1.0).max(1.0);
+        for (i, bucket) in buckets.iter_mut().enumerate() {
+            let log_lower = log_min_frequency + i as f32 * log_bucket_width;
+            let expected_bins = ((log_lower + log_bucket_width).exp() - log_lower.exp()) / bin_hz;
+            if self.filled[i] && expected_bins >= 1.0 {
+                if let BinSum::Power(power_scale) = sum {
+                    *bucket = (*bucket * power_scale).sqrt();
+                }
+                continue;
+            }
+            let log_center = log_lower + 0.5 * log_bucket_width;
+            *bucket = interpolate(magnitudes, log_center, bin_hz, first_bin, last_bin);
+            *bucket *= match sum {
+                BinSum::Magnitude => expected_bins,
+                BinSum::Power(power_scale) => (expected_bins * power_scale).sqrt(),
+            };
+        }
+        true
+    }
+}
+
+// Magnitude between the two bins around a frequency, linear on the log
+// frequency axis to match the bucket layout
+fn interpolate(
+    magnitudes: &[f32],
+    log_frequency: f32,
+    bin_hz: f32,
+    first_bin: usize,
+    last_bin: usize,
+) -> f32 {
+    let position = (log_frequency.exp() / bin_hz).clamp(first_bin as f32, last_bin as f32);
+    let lower = (position.floor() as usize).min(last_bin - 1);
+    let upper = lower + 1;
+
+    let log_lower = (lower as f32 * bin_hz).ln();
+    let log_upper = (upper as f32 * bin_hz).ln();
+    let fraction = ((log_frequency - log_lower) / (log_upper - log_lower)).clamp(0.0, 1.0);
+
+    let lower_value = magnitudes[lower];
+    let upper_value = magnitudes[upper];
+    lower_value + (upper_value - lower_value) * fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIN_HZ: f32 = 48_000.0 / 2048.0;
+    const NUM_BINS: usize = 1025;
+    const NUM_BUCKETS: usize = 256;
+    const FREQUENCY_MIN: f32 = 20.0;
+    const FREQUENCY_MAX: f32 = 20_000.0;
+
+    // Buckets narrower than a bin with their edges, skipping those whose
+    // center lies below the first bin
+    fn narrow_buckets() -> impl Iterator<Item = (usize, f32, f32)> {
+        (0..NUM_BUCKETS)
+            .map(|i| {
+                let (lower, upper) = bucket_edges(i, NUM_BUCKETS, FREQUENCY_MIN, FREQUENCY_MAX);
+                (i, lower, upper)
+            })
+            .take_while(|&(_, lower, upper)| upper - lower < BIN_HZ)
+            .filter(|&(_, lower, upper)| (lower * upper).sqrt() >= BIN_HZ)
+    }
+
+    fn bucketize(magnitudes: &[f32], sum: BinSum) -> Vec<f32> {
+        let mut buckets = vec![0.0; NUM_BUCKETS];
+        assert!(Bucketizer::default().bucketize(
+            magnitudes,
+            BIN_HZ,
+            (FREQUENCY_MIN, FREQUENCY_MAX),
+            sum,
+            &mut buckets,
+        ));
+        buckets
+    }
+
+    // A spectrum linear on the log frequency axis is read exactly at the
+    // bucket centers, scaled by the share of a bin each bucket covers
+    #[test]
+    fn narrow_buckets_interpolate_at_their_center() {
+        let magnitudes: Vec<f32> = (0..NUM_BINS)
+            .map(|bin| (bin.max(1) as f32 * BIN_HZ).ln())
+            .collect();
+        let buckets = bucketize(&magnitudes, BinSum::Magnitude);
+
+        assert!(narrow_buckets().count() > 32);
+        for (i, lower, upper) in narrow_buckets() {
+            let expected = (lower * upper).sqrt().ln() * (upper - lower) / BIN_HZ;
+            assert!(
+                (buckets[i] / expected - 1.0).abs() < 1e-3,
+                "bucket {} reads {} instead of {}",
+                i,
+                buckets[i],
+                expected
+            );
+        }
+    }
+
+    // Narrow buckets that catch a bin only now and then are neither empty
+    // nor spiking on a flat spectrum, in either summing mode
+    #[test]
+    fn flat_spectrum_leaves_no_gaps_in_narrow_buckets() {
+        let magnitudes = vec![1.0; NUM_BINS];
+        for sum in [BinSum::Magnitude, BinSum::Power(2.0)] {
+            let buckets = bucketize(&magnitudes, sum);
+            for (i, lower, upper) in narrow_buckets() {
+                let expected_bins = (upper - lower) / BIN_HZ;
+                let expected = match sum {
+                    BinSum::Magnitude => expected_bins,
+                    BinSum::Power(scale) => (expected_bins * scale).sqrt(),
+                };
+                assert!(
+                    (buckets[i] / expected - 1.0).abs() < 1e-3,
+                    "{:?} bucket {} reads {} instead of {}",
+                    sum,
+                    i,
+                    buckets[i],
+                    expected
+                );
+            }
+        }
+    }
+}
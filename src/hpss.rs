@@ -0,0 +1,99 @@
+use crate::MyConfig;
+
+pub const MIN_HPSS_FRAMES: usize = 3;
+pub const MAX_HPSS_FRAMES: usize = 64;
+pub const MIN_HPSS_KERNEL: usize = 3;
+pub const MAX_HPSS_KERNEL: usize = 128;
+
+// Exponent of the soft masks; higher values give a harder split
+const MASK_POWER: f32 = 2.0;
+
+/// Median-filter harmonic/percussive separation on the FFT bins. Sustained
+/// partials are steady across the recent frames while hits are broadband
+/// within a frame, so a median over time and one over neighboring bins
+/// estimate the two parts and split every bin between them. Working on the
+/// bins keeps the frequency median a few tens of hertz wide, where a median
+/// over buckets would span octaves.
+#[derive(Default)]
+pub struct HarmonicPercussiveSeparator {
+    // Ring buffer of the most recent spectra
+    history: Vec<Vec<f32>>,
+    next_frame: usize,
+    filled_frames: usize,
+    median_window: Vec<f32>,
+    harmonic: Vec<f32>,
+}
+
+impl HarmonicPercussiveSeparator {
+    /// Replaces the bin magnitudes in `spectrum` by their harmonic part and
+    /// writes the percussive part to `percussive`. The two always add up to
+    /// the input.
+    pub fn separate(&mut self, spectrum: &mut [f32], percussive: &mut Vec<f32>, config: &MyConfig) {
+        let num_bins = spectrum.len();
+        let frames = config.hpss_frames.clamp(MIN_HPSS_FRAMES, MAX_HPSS_FRAMES);
+        if self.history.len() != frames
+            || self
+                .history
+                .first()
+                .is_some_and(|frame| frame.len() != num_bins)
+        {
+            self.history = vec![vec![0.0; num_bins]; frames];
+            self.next_frame = 0;
+            self.filled_frames = 0;
+        }
+
+        self.history[self.next_frame].copy_from_slice(spectrum);
+        self.next_frame = (self.next_frame + 1) % frames;
+        self.filled_frames = (self.filled_frames + 1).min(frames);
+
+        // Median over time, per bin
+        self.harmonic.resize(num_bins, 0.0);
+        for (i, harmonic) in self.harmonic.iter_mut().enumerate() {
+            self.median_window.clear();
+            self.median_window.extend(
+                self.history[..self.filled_frames]
+                    .iter()
+                    .map(|frame| frame[i]),
+            );
+            *harmonic = median(&mut self.median_window);
+        }
+
+        // Median over frequency within the newest frame
+        percussive.resize(num_bins, 0.0);
+        let half_kernel = config.hpss_kernel.clamp(MIN_HPSS_KERNEL, MAX_HPSS_KERNEL) / 2;
+        for (i, percussive) in percussive.iter_mut().enumerate() {
+            let start = i.saturating_sub(half_kernel);
+            let end = (i + half_kernel + 1).min(num_bins);
+            self.median_window.clear();
+            self.median_window.extend_from_slice(&spectrum[start..end]);
+            *percussive = median(&mut self.median_window);
+        }
+
+        // Soft masks share each bin in proportion to the two estimates
+        for ((bin, percussive), harmonic) in spectrum
+            .iter_mut()
+            .zip(percussive.iter_mut())
+            .zip(&self.harmonic)
+        {
+            let harmonic_weight = harmonic.powf(MASK_POWER);
+            let percussive_weight = percussive.powf(MASK_POWER);
+            let total = harmonic_weight + percussive_weight;
+            let harmonic_mask = if total > 0.0 {
+                harmonic_weight / total
+            } else {
+                0.5
+            };
+
+            *percussive = *bin * (1.0 - harmonic_mask);
+            *bin *= harmonic_mask;
+        }
+    }
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let middle = values.len() / 2;
+    *values.select_nth_unstable_by(middle, f32::total_cmp).1
+}
@@ -0,0 +1,409 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use crate::audio_capture::AudioProcessedEvent;
+
+// Loudness is measured on 100 ms sub-blocks; the momentary window spans 4 of
+// them and the short-term window 30 (EBU Tech 3341)
+const SUB_BLOCK_SECONDS: f64 = 0.1;
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+
+// Gating of the integrated loudness (ITU-R BS.1770-4)
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+// Gating blocks are kept in a histogram so memory stays constant over long sessions
+const HISTOGRAM_STEP_LU: f64 = 0.1;
+const HISTOGRAM_MAX_LUFS: f64 = 10.0;
+
+// True peak is estimated by 4x oversampling with a windowed-sinc interpolator
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// Current levels, all in dB. Values are negative infinity until enough
+/// audio has been measured.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Loudness {
+    pub momentary_lufs: f32,
+    pub short_term_lufs: f32,
+    pub integrated_lufs: f32,
+    /// Unweighted RMS over the momentary window, left then right
+    pub rms_db: [f32; 2],
+    /// Highest true peak since the last reset, left then right
+    pub true_peak_db: [f32; 2],
+}
+
+impl Default for Loudness {
+    fn default() -> Self {
+        Loudness {
+            momentary_lufs: f32::NEG_INFINITY,
+            short_term_lufs: f32::NEG_INFINITY,
+            integrated_lufs: f32::NEG_INFINITY,
+            rms_db: [f32::NEG_INFINITY; 2],
+            true_peak_db: [f32::NEG_INFINITY; 2],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+
+    /// Gain of the filter at `frequency`, evaluated on the unit circle.
+    pub fn magnitude(&self, frequency: f64, sample_rate: u32) -> f64 {
+        let omega = 2.0 * PI * frequency / sample_rate as f64;
+        let (sin1, cos1) = omega.sin_cos();
+        let (sin2, cos2) = (2.0 * omega).sin_cos();
+
+        let numerator_re = self.b0 + self.b1 * cos1 + self.b2 * cos2;
+        let numerator_im = -(self.b1 * sin1 + self.b2 * sin2);
+        let denominator_re = 1.0 + self.a1 * cos1 + self.a2 * cos2;
+        let denominator_im = -(self.a1 * sin1 + self.a2 * sin2);
+        ((numerator_re * numerator_re + numerator_im * numerator_im)
+            / (denominator_re * denominator_re + denominator_im * denominator_im))
+            .sqrt()
+    }
+}
+
+// The two K-weighting stages, a high shelf modelling the head followed by a
+// high-pass, derived for the actual sample rate rather than the tabulated 48 kHz
+pub fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let sample_rate = sample_rate as f64;
+
+    let k = (PI * 1681.974450955533 / sample_rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b0: (vh + vb * k / q + k * k) / a0,
+        b1: 2.0 * (k * k - vh) / a0,
+        b2: (vh - vb * k / q + k * k) / a0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+        ..Default::default()
+    };
+
+    let k = (PI * 38.13547087602444 / sample_rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b0: 1.0,
+        b1: -2.0,
+        b2: 1.0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+        ..Default::default()
+    };
+
+    [shelf, high_pass]
+}
+
+// Polyphase interpolation filter, one row of taps per oversampled phase
+fn interpolation_filter() -> [[f32; TAPS_PER_PHASE]; OVERSAMPLING] {
+    let len = OVERSAMPLING * TAPS_PER_PHASE;
+    let center = (len - 1) as f64 / 2.0;
+    let mut phases = [[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+
+    for (phase, taps) in phases.iter_mut().enumerate() {
+        for (k, tap) in taps.iter_mut().enumerate() {
+            let n = phase + k * OVERSAMPLING;
+            let x = (n as f64 - center) / OVERSAMPLING as f64;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let window = 0.5 - 0.5 * (2.0 * PI * (n as f64 + 0.5) / len as f64).cos();
+            *tap = (sinc * window) as f32;
+        }
+        // Unity gain per phase so a constant signal keeps its level
+        let sum: f32 = taps.iter().sum();
+        for tap in taps.iter_mut() {
+            *tap /= sum;
+        }
+    }
+    phases
+}
+
+#[derive(Clone, Copy, Default)]
+struct SubBlock {
+    // Sum of the K-weighted mean squares of both channels
+    weighted: f64,
+    // Unweighted mean square per channel
+    squares: [f64; 2],
+}
+
+#[derive(Resource)]
+pub struct LoudnessMeter {
+    sample_rate: u32,
+    filters: [[Biquad; 2]; 2],
+    interpolator: [[f32; TAPS_PER_PHASE]; OVERSAMPLING],
+    // Newest sample first
+    peak_history: [[f32; TAPS_PER_PHASE]; 2],
+    true_peak: [f32; 2],
+    sub_block_len: usize,
+    current: SubBlock,
+    current_len: usize,
+    sub_blocks: VecDeque<SubBlock>,
+    // Number and summed power of gating blocks per loudness step
+    histogram: Vec<(u64, f64)>,
+}
+
+impl Default for LoudnessMeter {
+    fn default() -> Self {
+        LoudnessMeter::new(48_000)
+    }
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32) -> Self {
+        let filters = k_weighting(sample_rate);
+        let histogram_len =
+            ((HISTOGRAM_MAX_LUFS - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU).ceil() as usize;
+
+        LoudnessMeter {
+            sample_rate,
+            filters: [filters, filters],
+            interpolator: interpolation_filter(),
+            peak_history: [[0.0; TAPS_PER_PHASE]; 2],
+            true_peak: [0.0; 2],
+            sub_block_len: ((sample_rate as f64 * SUB_BLOCK_SECONDS).round() as usize).max(1),
+            current: SubBlock::default(),
+            current_len: 0,
+            sub_blocks: VecDeque::with_capacity(SHORT_TERM_SUB_BLOCKS),
+            histogram: vec![(0, 0.0); histogram_len],
+        }
+    }
+
+    /// Clears all measurements, including the integrated loudness and the peak hold.
+    pub fn reset(&mut self) {
+        *self = LoudnessMeter::new(self.sample_rate);
+    }
+
+    pub fn process(&mut self, left: &[f32], right: &[f32], sample_rate: u32) {
+        if sample_rate != self.sample_rate {
+            *self = LoudnessMeter::new(sample_rate);
+        }
+
+        for (&left_sample, &right_sample) in left.iter().zip(right.iter()) {
+            for (channel, sample) in [left_sample, right_sample].into_iter().enumerate() {
+                let weighted = self.filters[channel]
+                    .iter_mut()
+                    .fold(sample as f64, |value, filter| filter.process(value));
+                self.current.weighted += weighted * weighted;
+                self.current.squares[channel] += sample as f64 * sample as f64;
+                self.update_true_peak(channel, sample);
+            }
+
+            self.current_len += 1;
+            if self.current_len == self.sub_block_len {
+                self.finish_sub_block();
+            }
+        }
+    }
+
+    fn update_true_peak(&mut self, channel: usize, sample: f32) {
+        let history = &mut self.peak_history[channel];
+        history.copy_within(0..TAPS_PER_PHASE - 1, 1);
+        history[0] = sample;
+
+        let mut peak = self.true_peak[channel].max(sample.abs());
+        for taps in &self.interpolator {
+            let value: f32 = taps
+                .iter()
+                .zip(history.iter())
+                .map(|(tap, x)| tap * x)
+                .sum();
+            peak = peak.max(value.abs());
+        }
+        self.true_peak[channel] = peak;
+    }
+
+    fn finish_sub_block(&mut self) {
+        let len = self.current_len as f64;
+        let sub_block = SubBlock {
+            weighted: self.current.weighted / len,
+            squares: [self.current.squares[0] / len, self.current.squares[1] / len],
+        };
+        self.current = SubBlock::default();
+        self.current_len = 0;
+
+        if self.sub_blocks.len() == SHORT_TERM_SUB_BLOCKS {
+            self.sub_blocks.pop_front();
+        }
+        self.sub_blocks.push_back(sub_block);
+
+        // Every completed 400 ms window is a gating block, overlapping its
+        // predecessor by 75%
+        if let Some(power) = self.mean_power(MOMENTARY_SUB_BLOCKS) {
+            let loudness = power_to_lufs(power);
+            if loudness >= ABSOLUTE_GATE_LUFS {
+                let bin = self.histogram_bin(loudness);
+                self.histogram[bin].0 += 1;
+                self.histogram[bin].1 += power;
+            }
+        }
+    }
+
+    fn histogram_bin(&self, loudness: f64) -> usize {
+        (((loudness - ABSOLUTE_GATE_LUFS) / HISTOGRAM_STEP_LU).max(0.0) as usize)
+            .min(self.histogram.len() - 1)
+    }
+
+    // Mean K-weighted power of the newest `count` sub-blocks, if that many exist
+    fn mean_power(&self, count: usize) -> Option<f64> {
+        if self.sub_blocks.len() < count {
+            return None;
+        }
+        let sum: f64 = self
+            .sub_blocks
+            .iter()
+            .rev()
+            .take(count)
+            .map(|b| b.weighted)
+            .sum();
+        Some(sum / count as f64)
+    }
+
+    fn integrated_lufs(&self) -> f64 {
+        let gated_mean = |first_bin: usize| {
+            let (count, power) = self.histogram[first_bin..]
+                .iter()
+                .fold((0u64, 0.0), |(count, power), &(n, p)| {
+                    (count + n, power + p)
+                });
+            (count > 0).then(|| power / count as f64)
+        };
+
+        let Some(absolute_gated) = gated_mean(0) else {
+            return f64::NEG_INFINITY;
+        };
+        let relative_gate = power_to_lufs(absolute_gated) + RELATIVE_GATE_LU;
+        match gated_mean(self.histogram_bin(relative_gate)) {
+            Some(power) => power_to_lufs(power),
+            None => f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn levels(&self) -> Loudness {
+        let momentary = self.mean_power(MOMENTARY_SUB_BLOCKS);
+        let short_term = self.mean_power(SHORT_TERM_SUB_BLOCKS);
+
+        let mut rms_db = [f32::NEG_INFINITY; 2];
+        if self.sub_blocks.len() >= MOMENTARY_SUB_BLOCKS {
+            for (channel, rms) in rms_db.iter_mut().enumerate() {
+                let mean_square = self
+                    .sub_blocks
+                    .iter()
+                    .rev()
+                    .take(MOMENTARY_SUB_BLOCKS)
+                    .map(|b| b.squares[channel])
+                    .sum::<f64>()
+                    / MOMENTARY_SUB_BLOCKS as f64;
+                *rms = (10.0 * mean_square.log10()) as f32;
+            }
+        }
+
+        Loudness {
+            momentary_lufs: momentary.map_or(f64::NEG_INFINITY, power_to_lufs) as f32,
+            short_term_lufs: short_term.map_or(f64::NEG_INFINITY, power_to_lufs) as f32,
+            integrated_lufs: self.integrated_lufs() as f32,
+            rms_db,
+            true_peak_db: self.true_peak.map(|peak| 20.0 * peak.log10()),
+        }
+    }
+}
+
+fn power_to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+pub fn loudness_system(
+    mut sample_events: EventReader<AudioProcessedEvent>,
+    mut meter: ResMut<LoudnessMeter>,
+    mut loudness: ResMut<Loudness>,
+) {
+    for event in sample_events.read() {
+        meter.process(&event.left, &event.right, event.sample_rate);
+    }
+    *loudness = meter.levels();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const BLOCK_LEN: usize = 480;
+
+    // Feeds the same sine to both channels; `level_db` of None is silence
+    fn feed(meter: &mut LoudnessMeter, level_db: Option<f64>, seconds: f64, frequency: f64) {
+        let amplitude = level_db.map_or(0.0, |db| 10f64.powf(db / 20.0));
+        let samples: Vec<f32> = (0..(seconds * SAMPLE_RATE as f64) as usize)
+            .map(|n| {
+                let phase = 2.0 * PI * frequency * n as f64 / SAMPLE_RATE as f64 + PI / 4.0;
+                (amplitude * phase.sin()) as f32
+            })
+            .collect();
+        for block in samples.chunks(BLOCK_LEN) {
+            meter.process(block, block, SAMPLE_RATE);
+        }
+    }
+
+    fn assert_integrated(meter: &LoudnessMeter, expected: f32, case: &str) {
+        let integrated = meter.levels().integrated_lufs;
+        assert!(
+            (integrated - expected).abs() < 0.1,
+            "{}: {} LUFS",
+            case,
+            integrated
+        );
+    }
+
+    // EBU Tech 3341 test signals 1 to 3: a stereo 1 kHz sine at -23 dBFS
+    // reads -23 LUFS however much silence or quieter audio surrounds it
+    #[test]
+    fn integrated_loudness_gates_silence_and_quiet_passages() {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE);
+        feed(&mut meter, Some(-23.0), 20.0, 1000.0);
+        assert_integrated(&meter, -23.0, "steady sine");
+
+        feed(&mut meter, None, 20.0, 1000.0);
+        assert_integrated(&meter, -23.0, "after silence");
+
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE);
+        feed(&mut meter, Some(-36.0), 10.0, 1000.0);
+        feed(&mut meter, Some(-23.0), 60.0, 1000.0);
+        feed(&mut meter, Some(-36.0), 10.0, 1000.0);
+        assert_integrated(&meter, -23.0, "quiet passages");
+    }
+
+    // EBU Tech 3341 test signal 15: a quarter of the sample rate sampled 45
+    // degrees off its peaks, so every sample lies 3 dB below the true peak
+    #[test]
+    fn true_peak_finds_inter_sample_peaks() {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE);
+        feed(&mut meter, Some(-6.0), 1.0, SAMPLE_RATE as f64 / 4.0);
+        let true_peak = meter.levels().true_peak_db;
+        for peak in true_peak {
+            assert!(peak > -6.4 && peak < -5.8, "true peak {} dBTP", peak);
+        }
+    }
+}
@@ -10,28 +10,64 @@ use bevy_egui::EguiPlugin;
 
 mod audio_capture;
 mod audio_processing;
+mod band_energy;
+mod beat_detection;
 mod cfg;
+mod chroma;
+mod constant_q;
+mod dsp_worker;
+mod fft;
+mod hpss;
+mod loudness;
 mod materials;
+mod multi_resolution;
+mod noise_floor;
+mod pipeline;
+mod spectral_features;
+mod spectrogram;
+mod stereo;
+mod tempo;
 mod ui;
+mod vectorscope;
 mod visualization;
+mod waveform;
+mod weighting;
 
-use crate::audio_capture::{audio_capture_startup_system, AudioReceiver};
-use crate::audio_processing::{audio_event_system, AudioVisualizerState};
+use crate::audio_capture::{audio_capture_startup_system, AudioProcessedEvent, AudioReceiver};
+use crate::audio_processing::{audio_event_system, AudioVisualizerState, SpectrumFrames};
+use crate::band_energy::{band_energy_system, BandEnergies, BandEnergyTracker, MAX_SHADER_BANDS};
+use crate::beat_detection::{onset_detection_system, BeatEvent, OnsetDetector, OnsetEvent};
 use crate::cfg::*;
+use crate::chroma::{chroma_material_system, chroma_system, Chroma, ChromaTracker};
+use crate::dsp_worker::DspWorker;
+use crate::loudness::{loudness_system, Loudness, LoudnessMeter};
 use crate::materials::{BarEntity, BarMaterial};
+use crate::materials::{ChromaEntity, ChromaMaterial};
+use crate::materials::{OscilloscopeEntity, OscilloscopeMaterial};
+use crate::materials::{SpectrogramEntity, SpectrogramMaterial};
+use crate::materials::{VectorscopeEntity, VectorscopeMaterial};
 use crate::materials::{CircleSplitEntity, CircleSplitMaterial};
 use crate::materials::{PolygonEntity, PolygonMaterial};
 use crate::materials::{StringEntity, StringMaterial};
 use crate::materials::{WaveEntity, WaveMaterial};
+use crate::spectral_features::{
+    spectral_features_system, SpectralFeatureExtractor, SpectralFeatures,
+};
+use crate::spectrogram::{spectrogram_system, SpectrogramHistory};
+use crate::stereo::{stereo_analysis_system, StereoAnalysis, StereoAnalyzer};
+use crate::tempo::{tempo_tracking_system, TempoEstimate, TempoTracker};
 use crate::ui::{Colors, UIPlugin};
+use crate::vectorscope::{vectorscope_system, VectorscopeTrace};
 use crate::visualization::{
-    spawn_visualization, visualization_toggle_system, window_resized_event, VisualizationType,
+    spawn_visualization, visualization_toggle_system, visualizer_uniform_system,
+    window_resized_event, VisualizationType,
 };
+use crate::waveform::{oscilloscope_material_system, waveform_system, Waveform, WaveformBuffer};
 use cpal::available_hosts;
 use cpal::traits::{DeviceTrait, HostTrait};
 
-const ARRAY_UNIFORM_SIZE: usize = 16;
-const NUM_BUCKETS: usize = ARRAY_UNIFORM_SIZE * 4;
+const MIN_BUCKETS: usize = 8;
+const MAX_BUCKETS: usize = 1024;
 
 #[derive(Resource)]
 pub struct CfgResource(MyConfig);
@@ -49,10 +85,12 @@ pub enum AudioVizSystem {
 }
 
 fn main() {
-    let config = match confy::load("bevy_audioviz", "config") {
+    let mut config = match confy::load("bevy_audioviz", "config") {
         Ok(config) => config,
         Err(_) => MyConfig::default(),
     };
+    // A hand-edited config may list more bands than the shaders receive
+    config.energy_bands.truncate(MAX_SHADER_BANDS);
     println!(
         "Config file location: {:#?}",
         confy::get_configuration_file_path("bevy_audioviz", "config").unwrap()
@@ -86,12 +124,34 @@ fn main() {
         .add_plugins(LogDiagnosticsPlugin::default())
         .add_plugins(FrameTimeDiagnosticsPlugin)
         .add_plugins(UIPlugin)
-        .insert_resource(AudioVisualizerState::new(NUM_BUCKETS))
+        .insert_resource(AudioVisualizerState::new(config.num_buckets))
         .insert_resource(CfgResource(config))
         .insert_resource(GUIToggle::default())
         .insert_resource(Colors::default())
         .init_resource::<AudioReceiver>()
+        .init_resource::<DspWorker>()
         .init_resource::<VisualizationType>()
+        .init_resource::<OnsetDetector>()
+        .init_resource::<TempoTracker>()
+        .init_resource::<TempoEstimate>()
+        .init_resource::<ChromaTracker>()
+        .init_resource::<Chroma>()
+        .init_resource::<LoudnessMeter>()
+        .init_resource::<Loudness>()
+        .init_resource::<StereoAnalyzer>()
+        .init_resource::<StereoAnalysis>()
+        .init_resource::<WaveformBuffer>()
+        .init_resource::<Waveform>()
+        .init_resource::<SpectrogramHistory>()
+        .init_resource::<VectorscopeTrace>()
+        .init_resource::<SpectralFeatureExtractor>()
+        .init_resource::<SpectralFeatures>()
+        .init_resource::<BandEnergyTracker>()
+        .init_resource::<BandEnergies>()
+        .init_resource::<SpectrumFrames>()
+        .add_event::<AudioProcessedEvent>()
+        .add_event::<OnsetEvent>()
+        .add_event::<BeatEvent>()
         .add_systems(Startup, setup)
         .configure_sets(Update, (
             AudioVizSystem::Audio,
@@ -100,7 +160,22 @@ fn main() {
         ))
         .add_systems(Update, (
             audio_capture_startup_system,
-            audio_event_system
+            tempo_tracking_system.before(audio_event_system),
+            audio_event_system,
+            onset_detection_system.after(audio_event_system),
+            chroma_system.after(audio_event_system),
+            chroma_material_system.after(chroma_system),
+            loudness_system.after(audio_event_system),
+            stereo_analysis_system.after(audio_event_system),
+            waveform_system.after(audio_event_system),
+            oscilloscope_material_system.after(waveform_system),
+            spectrogram_system.after(audio_event_system),
+            vectorscope_system.after(audio_event_system),
+            spectral_features_system.after(audio_event_system),
+            band_energy_system.after(audio_event_system),
+            visualizer_uniform_system
+                .after(spectral_features_system)
+                .after(band_energy_system)
         ).in_set(AudioVizSystem::Audio))
         .add_systems(Update, (
             spawn_visualization,
@@ -116,11 +191,19 @@ fn main() {
         .init_resource::<CircleSplitEntity>()
         .init_resource::<PolygonEntity>()
         .init_resource::<WaveEntity>()
+        .init_resource::<ChromaEntity>()
+        .init_resource::<OscilloscopeEntity>()
+        .init_resource::<SpectrogramEntity>()
+        .init_resource::<VectorscopeEntity>()
         .add_plugins(Material2dPlugin::<BarMaterial>::default())
         .add_plugins(Material2dPlugin::<StringMaterial>::default())
         .add_plugins(Material2dPlugin::<CircleSplitMaterial>::default())
         .add_plugins(Material2dPlugin::<PolygonMaterial>::default())
         .add_plugins(Material2dPlugin::<WaveMaterial>::default())
+        .add_plugins(Material2dPlugin::<ChromaMaterial>::default())
+        .add_plugins(Material2dPlugin::<OscilloscopeMaterial>::default())
+        .add_plugins(Material2dPlugin::<SpectrogramMaterial>::default())
+        .add_plugins(Material2dPlugin::<VectorscopeMaterial>::default())
         .run();
 }
 
@@ -3,16 +3,17 @@ use bevy::reflect::TypePath;
 use bevy::render::render_resource::{AsBindGroup, ShaderRef};
 use bevy::sprite::Material2d;
 
-use crate::ARRAY_UNIFORM_SIZE;
+use crate::band_energy::BandEnergiesUniform;
+use crate::spectral_features::SpectralFeaturesUniform;
 
+// Implements `new` for a material with the viewport, colors and analysis
+// uniforms every visualizer shares, followed by its own initial fields
 #[macro_export]
-macro_rules! impl_material_new {
-    ($material_type:ty) => {
+macro_rules! impl_visualizer_material_new {
+    ($material_type:ty, $($field:ident: $value:expr),* $(,)?) => {
         impl $material_type {
             pub fn new(width: f32, height: f32, colors: &Colors) -> Self {
                 Self {
-                    left_data: [Vec4::new(0.0, 0.0, 0.0, 0.0); 16],
-                    right_data: [Vec4::new(0.0, 0.0, 0.0, 0.0); 16],
                     viewport_width: width,
                     viewport_height: height,
                     monochrome: if colors.monochrome { 1 } else { 0 },
@@ -37,11 +38,27 @@ macro_rules! impl_material_new {
                         ),
                         Vec4::ZERO,
                     ],
+                    spectral_features: [Vec4::ZERO; 2],
+                    band_energies: [Vec4::ZERO; 2],
+                    $($field: $value,)*
                 }
             }
         }
     };
 }
+
+#[macro_export]
+macro_rules! impl_material_new {
+    ($material_type:ty) => {
+        $crate::impl_visualizer_material_new!(
+            $material_type,
+            // Storage buffers cannot be empty, the real data arrives with the first analyzed frame
+            left_data: vec![0.0],
+            right_data: vec![0.0],
+            beat_phase: 0.0,
+        );
+    };
+}
 #[macro_export]
 macro_rules! prepare_material {
     ($material_type:ty, $materials:expr, $width:expr, $height:expr, $colors:expr) => {
@@ -52,37 +69,12 @@ macro_rules! prepare_material {
 #[macro_export]
 macro_rules! impl_one_channel_material_new {
     ($material_type:ty) => {
-        impl $material_type {
-            pub fn new(width: f32, height: f32, colors: &Colors) -> Self {
-                Self {
-                    normalized_data: [Vec4::new(0.0, 0.0, 0.0, 0.0); 16],
-                    viewport_width: width,
-                    viewport_height: height,
-                    monochrome: if colors.monochrome { 1 } else { 0 },
-                    colors: [
-                        Vec4::new(
-                            colors.color_start.r(),
-                            colors.color_start.g(),
-                            colors.color_start.b(),
-                            colors.color_start.a(),
-                        ),
-                        Vec4::new(
-                            colors.color_middle.r(),
-                            colors.color_middle.g(),
-                            colors.color_middle.b(),
-                            colors.color_middle.a(),
-                        ),
-                        Vec4::new(
-                            colors.color_end.r(),
-                            colors.color_end.g(),
-                            colors.color_end.b(),
-                            colors.color_end.a(),
-                        ),
-                        Vec4::ZERO,
-                    ],
-                }
-            }
-        }
+        $crate::impl_visualizer_material_new!(
+            $material_type,
+            // Storage buffers cannot be empty, the real data arrives with the first analyzed frame
+            normalized_data: vec![0.0],
+            beat_phase: 0.0,
+        );
     };
 }
 
@@ -101,12 +93,24 @@ pub struct WaveEntity(pub Option<Entity>);
 #[derive(Resource, Default)]
 pub struct PolygonEntity(pub Option<Entity>);
 
+#[derive(Resource, Default)]
+pub struct ChromaEntity(pub Option<Entity>);
+
+#[derive(Resource, Default)]
+pub struct OscilloscopeEntity(pub Option<Entity>);
+
+#[derive(Resource, Default)]
+pub struct SpectrogramEntity(pub Option<Entity>);
+
+#[derive(Resource, Default)]
+pub struct VectorscopeEntity(pub Option<Entity>);
+
 #[derive(Component, Debug, Clone, AsBindGroup, TypePath, Asset)]
 pub struct StringMaterial {
-    #[uniform(0)]
-    pub left_data: [Vec4; ARRAY_UNIFORM_SIZE],
-    #[uniform(1)]
-    pub right_data: [Vec4; ARRAY_UNIFORM_SIZE],
+    #[storage(0, read_only)]
+    pub left_data: Vec<f32>,
+    #[storage(1, read_only)]
+    pub right_data: Vec<f32>,
     #[uniform(2)]
     pub viewport_width: f32,
     #[uniform(3)]
@@ -115,6 +119,12 @@ pub struct StringMaterial {
     pub monochrome: u32,
     #[uniform(5)]
     pub colors: [Vec4; 4],
+    #[uniform(6)]
+    pub beat_phase: f32,
+    #[uniform(7)]
+    pub spectral_features: SpectralFeaturesUniform,
+    #[uniform(8)]
+    pub band_energies: BandEnergiesUniform,
 }
 impl Material2d for StringMaterial {
     fn fragment_shader() -> ShaderRef {
@@ -124,10 +134,10 @@ impl Material2d for StringMaterial {
 
 #[derive(Component, Debug, Clone, AsBindGroup, TypePath, Asset)]
 pub struct CircleSplitMaterial {
-    #[uniform(0)]
-    pub left_data: [Vec4; ARRAY_UNIFORM_SIZE],
-    #[uniform(1)]
-    pub right_data: [Vec4; ARRAY_UNIFORM_SIZE],
+    #[storage(0, read_only)]
+    pub left_data: Vec<f32>,
+    #[storage(1, read_only)]
+    pub right_data: Vec<f32>,
     #[uniform(2)]
     pub viewport_width: f32,
     #[uniform(3)]
@@ -136,6 +146,12 @@ pub struct CircleSplitMaterial {
     pub monochrome: u32,
     #[uniform(5)]
     pub colors: [Vec4; 4],
+    #[uniform(6)]
+    pub beat_phase: f32,
+    #[uniform(7)]
+    pub spectral_features: SpectralFeaturesUniform,
+    #[uniform(8)]
+    pub band_energies: BandEnergiesUniform,
 }
 impl Material2d for CircleSplitMaterial {
     fn fragment_shader() -> ShaderRef {
@@ -145,10 +161,10 @@ impl Material2d for CircleSplitMaterial {
 
 #[derive(Component, Debug, Clone, AsBindGroup, TypePath, Asset)]
 pub struct WaveMaterial {
-    #[uniform(0)]
-    pub left_data: [Vec4; ARRAY_UNIFORM_SIZE],
-    #[uniform(1)]
-    pub right_data: [Vec4; ARRAY_UNIFORM_SIZE],
+    #[storage(0, read_only)]
+    pub left_data: Vec<f32>,
+    #[storage(1, read_only)]
+    pub right_data: Vec<f32>,
     #[uniform(2)]
     pub viewport_width: f32,
     #[uniform(3)]
@@ -157,6 +173,12 @@ pub struct WaveMaterial {
     pub monochrome: u32,
     #[uniform(5)]
     pub colors: [Vec4; 4],
+    #[uniform(6)]
+    pub beat_phase: f32,
+    #[uniform(7)]
+    pub spectral_features: SpectralFeaturesUniform,
+    #[uniform(8)]
+    pub band_energies: BandEnergiesUniform,
 }
 impl Material2d for WaveMaterial {
     fn fragment_shader() -> ShaderRef {
@@ -166,8 +188,8 @@ impl Material2d for WaveMaterial {
 
 #[derive(Component, Debug, Clone, AsBindGroup, TypePath, Asset)]
 pub struct PolygonMaterial {
-    #[uniform(0)]
-    pub normalized_data: [Vec4; ARRAY_UNIFORM_SIZE],
+    #[storage(0, read_only)]
+    pub normalized_data: Vec<f32>,
     #[uniform(1)]
     pub viewport_width: f32,
     #[uniform(2)]
@@ -176,6 +198,12 @@ pub struct PolygonMaterial {
     pub monochrome: u32,
     #[uniform(4)]
     pub colors: [Vec4; 4],
+    #[uniform(5)]
+    pub beat_phase: f32,
+    #[uniform(6)]
+    pub spectral_features: SpectralFeaturesUniform,
+    #[uniform(7)]
+    pub band_energies: BandEnergiesUniform,
 }
 impl Material2d for PolygonMaterial {
     fn fragment_shader() -> ShaderRef {
@@ -185,8 +213,8 @@ impl Material2d for PolygonMaterial {
 
 #[derive(Component, Debug, Clone, AsBindGroup, TypePath, Asset)]
 pub struct BarMaterial {
-    #[uniform(0)]
-    pub normalized_data: [Vec4; ARRAY_UNIFORM_SIZE],
+    #[storage(0, read_only)]
+    pub normalized_data: Vec<f32>,
     #[uniform(1)]
     pub viewport_width: f32,
     #[uniform(2)]
@@ -195,9 +223,133 @@ pub struct BarMaterial {
     pub monochrome: u32,
     #[uniform(4)]
     pub colors: [Vec4; 4],
+    #[uniform(5)]
+    pub beat_phase: f32,
+    #[uniform(6)]
+    pub spectral_features: SpectralFeaturesUniform,
+    #[uniform(7)]
+    pub band_energies: BandEnergiesUniform,
 }
 impl Material2d for BarMaterial {
     fn fragment_shader() -> ShaderRef {
         "shaders/bar_fragment.wgsl".into()
     }
 }
+
+#[derive(Component, Debug, Clone, AsBindGroup, TypePath, Asset)]
+pub struct ChromaMaterial {
+    // Twelve pitch-class energies starting at C, packed four per vector
+    #[uniform(0)]
+    pub chroma: [Vec4; 3],
+    #[uniform(1)]
+    pub viewport_width: f32,
+    #[uniform(2)]
+    pub viewport_height: f32,
+    #[uniform(3)]
+    pub monochrome: u32,
+    #[uniform(4)]
+    pub colors: [Vec4; 4],
+    #[uniform(5)]
+    pub beat_phase: f32,
+    // Pitch class of the detected key, -1 while no key is detected
+    #[uniform(6)]
+    pub key_tonic: i32,
+    #[uniform(7)]
+    pub key_minor: u32,
+    #[uniform(8)]
+    pub spectral_features: SpectralFeaturesUniform,
+    #[uniform(9)]
+    pub band_energies: BandEnergiesUniform,
+}
+impl Material2d for ChromaMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/chroma_fragment.wgsl".into()
+    }
+}
+
+#[derive(Component, Debug, Clone, AsBindGroup, TypePath, Asset)]
+pub struct OscilloscopeMaterial {
+    // Time-domain samples between -1.0 and 1.0, not buckets
+    #[storage(0, read_only)]
+    pub left_data: Vec<f32>,
+    #[storage(1, read_only)]
+    pub right_data: Vec<f32>,
+    #[uniform(2)]
+    pub viewport_width: f32,
+    #[uniform(3)]
+    pub viewport_height: f32,
+    #[uniform(4)]
+    pub monochrome: u32,
+    #[uniform(5)]
+    pub colors: [Vec4; 4],
+    #[uniform(6)]
+    pub beat_phase: f32,
+    // Trace width in pixels
+    #[uniform(7)]
+    pub line_width: f32,
+    #[uniform(8)]
+    pub glow: f32,
+    #[uniform(9)]
+    pub spectral_features: SpectralFeaturesUniform,
+    #[uniform(10)]
+    pub band_energies: BandEnergiesUniform,
+}
+impl Material2d for OscilloscopeMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/oscilloscope_fragment.wgsl".into()
+    }
+}
+
+#[derive(Component, Debug, Clone, AsBindGroup, TypePath, Asset)]
+pub struct SpectrogramMaterial {
+    // Ring buffer of frames, one row per frame and one column per bucket
+    #[texture(0)]
+    pub history: Handle<Image>,
+    #[uniform(1)]
+    pub viewport_width: f32,
+    #[uniform(2)]
+    pub viewport_height: f32,
+    #[uniform(3)]
+    pub monochrome: u32,
+    #[uniform(4)]
+    pub colors: [Vec4; 4],
+    // Row of the history texture written last
+    #[uniform(5)]
+    pub newest_row: u32,
+    #[uniform(6)]
+    pub vertical: u32,
+    #[uniform(7)]
+    pub spectral_features: SpectralFeaturesUniform,
+    #[uniform(8)]
+    pub band_energies: BandEnergiesUniform,
+}
+impl Material2d for SpectrogramMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/spectrogram_fragment.wgsl".into()
+    }
+}
+
+#[derive(Component, Debug, Clone, AsBindGroup, TypePath, Asset)]
+pub struct VectorscopeMaterial {
+    // Square persistence image of the plotted sample pairs
+    #[texture(0)]
+    #[sampler(1)]
+    pub trace: Handle<Image>,
+    #[uniform(2)]
+    pub viewport_width: f32,
+    #[uniform(3)]
+    pub viewport_height: f32,
+    #[uniform(4)]
+    pub monochrome: u32,
+    #[uniform(5)]
+    pub colors: [Vec4; 4],
+    #[uniform(6)]
+    pub spectral_features: SpectralFeaturesUniform,
+    #[uniform(7)]
+    pub band_energies: BandEnergiesUniform,
+}
+impl Material2d for VectorscopeMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/vectorscope_fragment.wgsl".into()
+    }
+}
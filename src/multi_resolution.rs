@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+
+use crate::audio_processing::bucket_center_frequency;
+use crate::cfg::AnalyzerMode;
+use crate::MyConfig;
+
+pub const MIN_FFT_SIZE: usize = 256;
+pub const MAX_FFT_SIZE: usize = 32768;
+
+/// The most recent samples of one analyzed channel, enough for the longest
+/// transform.
+#[derive(Default)]
+pub struct SampleHistory {
+    sample_rate: u32,
+    samples: VecDeque<f32>,
+}
+
+impl SampleHistory {
+    pub fn push(&mut self, samples: &[f32], sample_rate: u32, capacity: usize) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.samples.clear();
+        }
+        self.samples.extend(samples.iter().copied());
+
+        let excess = self.samples.len().saturating_sub(capacity);
+        self.samples.drain(..excess);
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Newest `len` samples, or all of them while the history is still filling.
+    pub fn latest(&mut self, len: usize) -> &[f32] {
+        let samples = self.samples.make_contiguous();
+        &samples[samples.len().saturating_sub(len)..]
+    }
+}
+
+/// Crossfades from the long-FFT `buckets` to the short-FFT `short_buckets`
+/// between the two crossover frequencies on the logarithmic axis, so the long
+/// transform resolves the bass and the short one keeps the treble responsive.
+/// Both must already be measured at the same level.
+pub fn crossfade(
+    buckets: &mut [f32],
+    short_buckets: &[f32],
+    (frequency_min, frequency_max): (f32, f32),
+    (crossover_min, crossover_max): (f32, f32),
+) {
+    let log_crossover_min = crossover_min.ln();
+    let log_crossover_max = crossover_max.max(crossover_min).ln();
+    let num_buckets = buckets.len();
+    for (i, (bucket, short)) in buckets.iter_mut().zip(short_buckets).enumerate() {
+        let log_frequency =
+            bucket_center_frequency(i, num_buckets, frequency_min, frequency_max).ln();
+        // Equal crossover points switch over without a blend
+        let blend = if log_crossover_max > log_crossover_min {
+            ((log_frequency - log_crossover_min) / (log_crossover_max - log_crossover_min))
+                .clamp(0.0, 1.0)
+        } else if log_frequency >= log_crossover_min {
+            1.0
+        } else {
+            0.0
+        };
+        *bucket += (short - *bucket) * blend;
+    }
+}
+
+/// Long and short transform lengths, the short one never exceeding the long one.
+pub fn fft_sizes(config: &MyConfig) -> (usize, usize) {
+    let long_size = config.long_fft_size.clamp(MIN_FFT_SIZE, MAX_FFT_SIZE);
+    let short_size = config.short_fft_size.clamp(MIN_FFT_SIZE, long_size);
+    (long_size, short_size)
+}
+
+/// Lengths of the block the FFT analyzes and of the short block of the
+/// multi-resolution FFT, or `None` for the constant-Q analyzer, which keeps
+/// its own history.
+pub fn block_lengths(config: &MyConfig) -> Option<(usize, Option<usize>)> {
+    match config.analyzer {
+        AnalyzerMode::ConstantQ => None,
+        AnalyzerMode::Fft if config.multi_resolution => {
+            let (long_size, short_size) = fft_sizes(config);
+            Some((long_size, Some(short_size)))
+        }
+        AnalyzerMode::Fft => Some((config.fft_size.clamp(MIN_FFT_SIZE, MAX_FFT_SIZE), None)),
+    }
+}
@@ -0,0 +1,131 @@
+use crate::audio_processing::smoothing_coefficient;
+use crate::cfg::NoiseFloorMode;
+use crate::pipeline::{Stage, StageContext, StageData};
+use crate::MyConfig;
+
+// The floor is the minimum of the smoothed level over the last several
+// seconds, tracked in sub-windows so old minima expire one at a time. Notes
+// and pads held for less than the window never become the floor.
+const WINDOW_SECONDS: f32 = 8.0;
+const SUBWINDOWS: usize = 8;
+const LEVEL_SMOOTHING_MS: f32 = 80.0;
+// The minimum of smoothed noise lies below its mean
+const MINIMUM_BIAS: f32 = 1.5;
+// Even once the window minimum rose, the floor follows it only this fast
+const MAX_RISE_DB_PER_SECOND: f32 = 6.0;
+// Lowest floor a rise is measured from, so a floor of zero can still rise
+const MIN_RISE_FLOOR: f32 = 1e-6;
+
+// A bucket opens 6 dB above the floor and closes again below 3 dB
+const OPEN_RATIO: f32 = 2.0;
+const CLOSE_RATIO: f32 = 1.41;
+
+// Fade time of the gate gain, short enough to keep transients but long
+// enough to avoid clicks in the display
+const GAIN_RAMP_MS: f32 = 20.0;
+
+/// Per-bucket noise floor tracker using minimum statistics, with a hysteresis
+/// gate. Buckets are either reduced by the floor or faded out while they stay
+/// close to it.
+#[derive(Default)]
+pub struct NoiseFloor {
+    mode: NoiseFloorMode,
+    threshold: f32,
+    hold_seconds: f32,
+    smoothed: Vec<f32>,
+    // Minimum of every bucket in each of the last completed sub-windows,
+    // `SUBWINDOWS` values per bucket
+    subwindow_minima: Vec<f32>,
+    current_minima: Vec<f32>,
+    subwindow_seconds: f32,
+    next_subwindow: usize,
+    floor: Vec<f32>,
+    open: Vec<bool>,
+    // Seconds an open bucket stays open after dropping below the close level
+    hold: Vec<f32>,
+    gain: Vec<f32>,
+}
+
+impl NoiseFloor {
+    // Starts from the gate threshold, so audio already playing is not taken
+    // for noise until it has lasted a whole window
+    fn reset(&mut self, num_buckets: usize) {
+        self.smoothed = vec![self.threshold; num_buckets];
+        self.subwindow_minima = vec![self.threshold; num_buckets * SUBWINDOWS];
+        self.current_minima = vec![f32::INFINITY; num_buckets];
+        self.subwindow_seconds = 0.0;
+        self.next_subwindow = 0;
+        self.floor = vec![self.threshold; num_buckets];
+        self.open = vec![false; num_buckets];
+        self.hold = vec![0.0; num_buckets];
+        self.gain = vec![0.0; num_buckets];
+    }
+
+    /// Updates the floor with a new frame covering `elapsed_seconds` and
+    /// removes it from the buckets.
+    pub fn apply(&mut self, buckets: &mut [f32], elapsed_seconds: f32) {
+        if self.floor.len() != buckets.len() {
+            self.reset(buckets.len());
+        }
+
+        // The oldest sub-window is replaced by the one just completed
+        self.subwindow_seconds += elapsed_seconds;
+        if self.subwindow_seconds >= WINDOW_SECONDS / SUBWINDOWS as f32 {
+            self.subwindow_seconds = 0.0;
+            for (i, minimum) in self.current_minima.iter_mut().enumerate() {
+                self.subwindow_minima[i * SUBWINDOWS + self.next_subwindow] = *minimum;
+                *minimum = f32::INFINITY;
+            }
+            self.next_subwindow = (self.next_subwindow + 1) % SUBWINDOWS;
+        }
+
+        let smoothing = smoothing_coefficient(LEVEL_SMOOTHING_MS, elapsed_seconds);
+        let max_rise = 10f32.powf(MAX_RISE_DB_PER_SECOND * elapsed_seconds / 20.0);
+        let ramp = smoothing_coefficient(GAIN_RAMP_MS, elapsed_seconds);
+
+        for (i, bucket) in buckets.iter_mut().enumerate() {
+            let value = *bucket;
+            self.smoothed[i] += (value - self.smoothed[i]) * smoothing;
+            self.current_minima[i] = self.current_minima[i].min(self.smoothed[i]);
+            let minimum = self.subwindow_minima[i * SUBWINDOWS..(i + 1) * SUBWINDOWS]
+                .iter()
+                .fold(self.current_minima[i], |minimum, &value| minimum.min(value))
+                * MINIMUM_BIAS;
+            self.floor[i] = minimum.min(self.floor[i].max(MIN_RISE_FLOOR) * max_rise);
+
+            // The gate threshold is an absolute minimum for the floor so
+            // digital silence and dither are always removed
+            let floor = self.floor[i].max(self.threshold);
+
+            if value > floor * OPEN_RATIO {
+                self.open[i] = true;
+                self.hold[i] = self.hold_seconds;
+            } else if value < floor * CLOSE_RATIO {
+                self.hold[i] -= elapsed_seconds;
+                if self.hold[i] <= 0.0 {
+                    self.open[i] = false;
+                }
+            }
+
+            let target_gain = if self.open[i] { 1.0 } else { 0.0 };
+            self.gain[i] += (target_gain - self.gain[i]) * ramp;
+
+            *bucket = match self.mode {
+                NoiseFloorMode::Subtract => (value - floor).max(0.0) * self.gain[i],
+                NoiseFloorMode::Gate => value * self.gain[i],
+            };
+        }
+    }
+}
+
+impl Stage for NoiseFloor {
+    fn configure(&mut self, config: &MyConfig) {
+        self.mode = config.noise_floor_mode;
+        self.threshold = config.gate_threshold;
+        self.hold_seconds = config.gate_hold_ms.max(0.0) / 1000.0;
+    }
+
+    fn process(&mut self, data: &mut StageData, context: &StageContext) {
+        self.apply(&mut data.buckets, context.elapsed_seconds);
+    }
+}
@@ -0,0 +1,443 @@
+use std::ops::{RangeBounds, RangeInclusive};
+
+use crate::audio_processing::{smooth, smoothing_coefficient};
+use crate::cfg::{AnalyzerMode, StageKind};
+use crate::constant_q::ConstantQ;
+use crate::fft::{BinSum, Bucketizer, FftAnalyzer, HannWindow};
+use crate::multi_resolution::crossfade;
+use crate::noise_floor::NoiseFloor;
+use crate::weighting::Weighting;
+use crate::MyConfig;
+use crate::{MAX_BUCKETS, MIN_BUCKETS};
+
+/// Where a stage sits in the pipeline. The phases always run in this order,
+/// stages can only be reordered within their own phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StagePhase {
+    /// Prepares the blocks of samples for the transform
+    Samples,
+    /// Turns the samples into a spectrum
+    Transform,
+    /// Maps the spectrum onto the buckets
+    Spectrum,
+    /// Refines the buckets of every analyzed frame, on the DSP worker
+    Buckets,
+    /// Prepares the buckets for the materials, once per rendered frame
+    Display,
+}
+
+pub fn stage_phase(kind: StageKind) -> StagePhase {
+    match kind {
+        StageKind::Window => StagePhase::Samples,
+        StageKind::Fft => StagePhase::Transform,
+        StageKind::Bucketize => StagePhase::Spectrum,
+        StageKind::Weighting | StageKind::Smoothing | StageKind::NoiseFloor => StagePhase::Buckets,
+        StageKind::Animate | StageKind::Normalize => StagePhase::Display,
+    }
+}
+
+/// Stages without which no buckets are produced at all. They can be moved but
+/// not removed.
+pub fn is_required(kind: StageKind) -> bool {
+    matches!(kind, StageKind::Fft | StageKind::Bucketize)
+}
+
+/// A block of samples and its spectrum.
+#[derive(Default)]
+pub struct TransformBlock {
+    pub samples: Vec<f32>,
+    /// Sum of the squared window weights, the block length while unwindowed
+    pub window_energy: f32,
+    /// Magnitude of every bin from DC to Nyquist, `bin_hz` apart
+    pub spectrum: Vec<f32>,
+    pub bin_hz: f32,
+    /// Spectrum power per unit of signal mean square
+    pub energy_gain: f32,
+}
+
+impl TransformBlock {
+    /// Copies the newest samples into the block, replacing the last spectrum.
+    pub fn fill(&mut self, samples: &[f32]) {
+        self.samples.clear();
+        self.samples.extend_from_slice(samples);
+        self.window_energy = samples.len() as f32;
+        self.spectrum.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.spectrum.clear();
+    }
+}
+
+/// Everything the stages of one channel pass on to each other. The buffers
+/// are kept from frame to frame, so a pipeline that has settled allocates
+/// nothing.
+#[derive(Default)]
+pub struct StageData {
+    /// Every sample captured since the previous frame
+    pub input: Vec<f32>,
+    pub sample_rate: u32,
+    pub block: TransformBlock,
+    /// Shorter block resolving the treble of the multi-resolution FFT, empty
+    /// otherwise
+    pub short_block: TransformBlock,
+    /// Bucket magnitudes on the logarithmic scale, empty until a stage
+    /// produces them
+    pub buckets: Vec<f32>,
+}
+
+/// What a stage may need besides the data itself.
+pub struct StageContext {
+    /// Time covered by the frame being processed
+    pub elapsed_seconds: f32,
+    /// How long the newest buckets have been available at the end of
+    /// `elapsed_seconds`, zero when they did not change. Only the display
+    /// stages run more often than the buckets change.
+    pub target_age_seconds: f32,
+}
+
+/// One step from the captured samples to the displayed buckets. Stages copy
+/// their parameters from the config whenever it changes and keep whatever
+/// state they need between frames.
+pub trait Stage: Send + Sync {
+    fn configure(&mut self, config: &MyConfig);
+    fn process(&mut self, data: &mut StageData, context: &StageContext);
+}
+
+#[derive(Default)]
+struct WindowStage {
+    window: HannWindow,
+    short_window: HannWindow,
+}
+
+impl Stage for WindowStage {
+    fn configure(&mut self, _config: &MyConfig) {}
+
+    fn process(&mut self, data: &mut StageData, _context: &StageContext) {
+        for (block, window) in [
+            (&mut data.block, &mut self.window),
+            (&mut data.short_block, &mut self.short_window),
+        ] {
+            if !block.samples.is_empty() {
+                window.apply(&mut block.samples);
+                block.window_energy = window.energy();
+            }
+        }
+    }
+}
+
+// Transforms the blocks with the FFT, or analyzes every captured sample with
+// the constant-Q analyzer, whose bins already are the buckets
+#[derive(Default)]
+struct FftStage {
+    analyzer: AnalyzerMode,
+    frequency_min: f32,
+    frequency_max: f32,
+    num_buckets: usize,
+    fft: FftAnalyzer,
+    constant_q: Option<ConstantQ>,
+}
+
+impl FftStage {
+    fn transform(fft: &mut FftAnalyzer, block: &mut TransformBlock, sample_rate: u32) {
+        block.spectrum.clear();
+        if let Some(fft_len) = fft.magnitudes(&block.samples, &mut block.spectrum) {
+            block.bin_hz = sample_rate as f32 / fft_len as f32;
+            block.energy_gain = fft_len as f32 * block.window_energy;
+        }
+    }
+}
+
+impl Stage for FftStage {
+    fn configure(&mut self, config: &MyConfig) {
+        self.analyzer = config.analyzer;
+        self.frequency_min = config.frequency_min;
+        self.frequency_max = config.frequency_max;
+        self.num_buckets = config.num_buckets.clamp(MIN_BUCKETS, MAX_BUCKETS);
+    }
+
+    fn process(&mut self, data: &mut StageData, _context: &StageContext) {
+        match self.analyzer {
+            AnalyzerMode::Fft => {
+                FftStage::transform(&mut self.fft, &mut data.block, data.sample_rate);
+                FftStage::transform(&mut self.fft, &mut data.short_block, data.sample_rate);
+            }
+            AnalyzerMode::ConstantQ => {
+                // Rebuilt whenever the sample rate or bucket layout changed
+                let up_to_date = self.constant_q.as_ref().is_some_and(|analyzer| {
+                    analyzer.matches(
+                        data.sample_rate,
+                        self.frequency_min,
+                        self.frequency_max,
+                        self.num_buckets,
+                    )
+                });
+                if !up_to_date {
+                    self.constant_q = Some(ConstantQ::new(
+                        data.sample_rate,
+                        self.frequency_min,
+                        self.frequency_max,
+                        self.num_buckets,
+                    ));
+                }
+                if let Some(analyzer) = &mut self.constant_q {
+                    analyzer.push_samples(&data.input);
+                    data.buckets.resize(self.num_buckets, 0.0);
+                    analyzer.analyze(&mut data.buckets);
+                }
+            }
+        }
+    }
+}
+
+// Sums the bins into the buckets. With a short block the two spectra are
+// measured as bucket power at the level of the long one and crossfaded.
+#[derive(Default)]
+struct BucketizeStage {
+    frequency_range: (f32, f32),
+    crossover: (f32, f32),
+    num_buckets: usize,
+    bucketizer: Bucketizer,
+    short_buckets: Vec<f32>,
+}
+
+impl Stage for BucketizeStage {
+    fn configure(&mut self, config: &MyConfig) {
+        self.frequency_range = (config.frequency_min, config.frequency_max);
+        self.crossover = (config.crossover_min, config.crossover_max);
+        self.num_buckets = config.num_buckets.clamp(MIN_BUCKETS, MAX_BUCKETS);
+    }
+
+    fn process(&mut self, data: &mut StageData, _context: &StageContext) {
+        let block = &data.block;
+        // The constant-Q analyzer leaves no spectrum to map
+        if block.spectrum.is_empty() {
+            return;
+        }
+
+        data.buckets.resize(self.num_buckets, 0.0);
+        let short_block = &data.short_block;
+        let sum = if short_block.spectrum.is_empty() {
+            BinSum::Magnitude
+        } else {
+            BinSum::Power(1.0)
+        };
+        if !self.bucketizer.bucketize(
+            &block.spectrum,
+            block.bin_hz,
+            self.frequency_range,
+            sum,
+            &mut data.buckets,
+        ) {
+            data.buckets.clear();
+            return;
+        }
+
+        if short_block.spectrum.is_empty() {
+            return;
+        }
+        self.short_buckets.resize(self.num_buckets, 0.0);
+        // The long transform alone still covers every bucket
+        if self.bucketizer.bucketize(
+            &short_block.spectrum,
+            short_block.bin_hz,
+            self.frequency_range,
+            BinSum::Power(block.energy_gain / short_block.energy_gain),
+            &mut self.short_buckets,
+        ) {
+            crossfade(
+                &mut data.buckets,
+                &self.short_buckets,
+                self.frequency_range,
+                self.crossover,
+            );
+        }
+    }
+}
+
+#[derive(Default)]
+struct WeightingStage {
+    weighting: Weighting,
+}
+
+impl Stage for WeightingStage {
+    fn configure(&mut self, config: &MyConfig) {
+        self.weighting = Weighting::new(config);
+    }
+
+    fn process(&mut self, data: &mut StageData, _context: &StageContext) {
+        self.weighting.apply(&mut data.buckets, data.sample_rate);
+    }
+}
+
+#[derive(Default)]
+struct SmoothingStage {
+    passes: u32,
+    size: u32,
+    temp_buffer: Vec<f32>,
+}
+
+impl Stage for SmoothingStage {
+    fn configure(&mut self, config: &MyConfig) {
+        self.passes = config.smoothing;
+        self.size = config.smoothing_size;
+    }
+
+    fn process(&mut self, data: &mut StageData, _context: &StageContext) {
+        smooth(
+            &mut data.buckets,
+            &mut self.temp_buffer,
+            self.passes,
+            self.size,
+        );
+    }
+}
+
+// Moves the displayed buckets towards the latest analysis results by the
+// wall-clock time of the rendered frame
+#[derive(Default)]
+struct AnimateStage {
+    attack_ms: f32,
+    release_ms: f32,
+    current: Vec<f32>,
+    previous_targets: Vec<f32>,
+}
+
+impl Stage for AnimateStage {
+    fn configure(&mut self, config: &MyConfig) {
+        self.attack_ms = config.attack_ms;
+        self.release_ms = config.release_ms;
+    }
+
+    fn process(&mut self, data: &mut StageData, context: &StageContext) {
+        let targets = &mut data.buckets;
+        // Start over from silence when the bucket count changed
+        if self.current.len() != targets.len() {
+            self.current.clear();
+            self.current.resize(targets.len(), 0.0);
+            self.previous_targets.clone_from(&self.current);
+        }
+
+        // Advance with the old targets up to the moment the newest buffer
+        // was captured, then with the new targets for the rest of the frame
+        let age_seconds = context.target_age_seconds.min(context.elapsed_seconds);
+        self.animate(context.elapsed_seconds - age_seconds);
+        self.previous_targets.clone_from(targets);
+        self.animate(age_seconds);
+
+        targets.copy_from_slice(&self.current);
+    }
+}
+
+impl AnimateStage {
+    fn animate(&mut self, elapsed_seconds: f32) {
+        let attack = smoothing_coefficient(self.attack_ms, elapsed_seconds);
+        let release = smoothing_coefficient(self.release_ms, elapsed_seconds);
+
+        for (&target, current) in self.previous_targets.iter().zip(self.current.iter_mut()) {
+            // Rising values follow the attack time, falling values the release time
+            let coefficient = if target > *current { attack } else { release };
+            *current += (target - *current) * coefficient;
+        }
+    }
+}
+
+struct NormalizeStage;
+
+impl Stage for NormalizeStage {
+    fn configure(&mut self, _config: &MyConfig) {}
+
+    fn process(&mut self, data: &mut StageData, _context: &StageContext) {
+        let max_value = data.buckets.iter().cloned().fold(f32::MIN, f32::max);
+
+        // Silence would otherwise divide by zero
+        if max_value <= 0.0 {
+            data.buckets.fill(0.0);
+            return;
+        }
+
+        for bucket in data.buckets.iter_mut() {
+            *bucket /= max_value;
+        }
+    }
+}
+
+fn new_stage(kind: StageKind) -> Box<dyn Stage> {
+    match kind {
+        StageKind::Window => Box::<WindowStage>::default(),
+        StageKind::Fft => Box::<FftStage>::default(),
+        StageKind::Bucketize => Box::<BucketizeStage>::default(),
+        StageKind::Weighting => Box::<WeightingStage>::default(),
+        StageKind::Smoothing => Box::<SmoothingStage>::default(),
+        StageKind::NoiseFloor => Box::<NoiseFloor>::default(),
+        StageKind::Animate => Box::<AnimateStage>::default(),
+        StageKind::Normalize => Box::new(NormalizeStage),
+    }
+}
+
+/// The configured stages of one channel within a range of phases, in order.
+/// The DSP worker runs the phases up to the buckets, the main thread the
+/// display phase.
+pub struct Pipeline {
+    phases: RangeInclusive<StagePhase>,
+    kinds: Vec<StageKind>,
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new(phases: RangeInclusive<StagePhase>) -> Self {
+        Pipeline {
+            phases,
+            kinds: Vec::new(),
+            stages: Vec::new(),
+        }
+    }
+
+    /// Reassembles the stages when they were added, removed or moved in the
+    /// GUI, and passes every stage its parameters.
+    pub fn configure(&mut self, config: &MyConfig) {
+        let mut kinds: Vec<StageKind> = config
+            .pipeline
+            .iter()
+            .copied()
+            .filter(|&kind| self.phases.contains(&stage_phase(kind)))
+            .collect();
+        // A hand-edited config still yields buckets in a valid order
+        for kind in StageKind::ALL {
+            if is_required(kind)
+                && self.phases.contains(&stage_phase(kind))
+                && !kinds.contains(&kind)
+            {
+                kinds.push(kind);
+            }
+        }
+        kinds.sort_by_key(|&kind| stage_phase(kind));
+
+        if self.kinds != kinds {
+            self.stages = kinds.iter().map(|&kind| new_stage(kind)).collect();
+            self.kinds = kinds;
+        }
+        for stage in self.stages.iter_mut() {
+            stage.configure(config);
+        }
+    }
+
+    pub fn process(&mut self, data: &mut StageData, context: &StageContext) {
+        self.process_phases(.., data, context);
+    }
+
+    /// Runs only the stages of the given phases.
+    pub fn process_phases(
+        &mut self,
+        phases: impl RangeBounds<StagePhase>,
+        data: &mut StageData,
+        context: &StageContext,
+    ) {
+        for (kind, stage) in self.kinds.iter().zip(self.stages.iter_mut()) {
+            if phases.contains(&stage_phase(*kind)) {
+                stage.process(data, context);
+            }
+        }
+    }
+}
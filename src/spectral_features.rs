@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+
+use crate::audio_processing::{bucket_center_frequency, SpectrumFrames};
+use crate::CfgResource;
+
+// Share of the spectral energy below the rolloff frequency
+const ROLLOFF_FRACTION: f32 = 0.85;
+
+/// Layout of the features in the shaders: centroid, spread, rolloff and
+/// flatness in the first vector, flux in the second.
+pub type SpectralFeaturesUniform = [Vec4; 2];
+
+/// Timbre descriptors of the latest analyzed frame, computed on the mono mix
+/// of the bucket magnitudes.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct SpectralFeatures {
+    /// Magnitude-weighted mean frequency in Hz, the perceived brightness
+    pub centroid: f32,
+    /// Magnitude-weighted standard deviation around the centroid in Hz
+    pub spread: f32,
+    /// Frequency in Hz below which most of the energy lies
+    pub rolloff: f32,
+    /// Geometric over arithmetic mean of the power, 0.0 for a pure tone and
+    /// 1.0 for white noise
+    pub flatness: f32,
+    /// Increase of the normalized spectrum since the previous frame
+    pub flux: f32,
+}
+
+impl SpectralFeatures {
+    /// Features scaled to roughly 0.0 to 1.0 for the shaders. Frequencies
+    /// are placed on the logarithmic bucket scale.
+    pub fn uniform(&self, frequency_min: f32, frequency_max: f32) -> SpectralFeaturesUniform {
+        let log_position = |frequency: f32| {
+            if frequency <= 0.0 {
+                return 0.0;
+            }
+            ((frequency / frequency_min).ln() / (frequency_max / frequency_min).ln())
+                .clamp(0.0, 1.0)
+        };
+        let spread = (self.spread / (frequency_max - frequency_min).max(1.0)).clamp(0.0, 1.0);
+
+        [
+            Vec4::new(
+                log_position(self.centroid),
+                spread,
+                log_position(self.rolloff),
+                self.flatness,
+            ),
+            Vec4::new(self.flux.clamp(0.0, 1.0), 0.0, 0.0, 0.0),
+        ]
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct SpectralFeatureExtractor {
+    // Previous frame divided by its norm, for the flux
+    previous: Vec<f32>,
+    // Center frequency of every bucket
+    frequencies: Vec<f32>,
+}
+
+impl SpectralFeatureExtractor {
+    fn process(
+        &mut self,
+        buckets: &[f32],
+        frequency_min: f32,
+        frequency_max: f32,
+    ) -> SpectralFeatures {
+        self.frequencies.clear();
+        self.frequencies.extend(
+            (0..buckets.len())
+                .map(|i| bucket_center_frequency(i, buckets.len(), frequency_min, frequency_max)),
+        );
+        let frequencies = &self.frequencies;
+
+        let magnitude_sum: f32 = buckets.iter().sum();
+        let energy: f32 = buckets.iter().map(|m| m * m).sum();
+        if magnitude_sum <= f32::EPSILON || energy <= f32::EPSILON {
+            self.previous.clear();
+            return SpectralFeatures::default();
+        }
+
+        let centroid = frequencies
+            .iter()
+            .zip(buckets.iter())
+            .map(|(frequency, magnitude)| frequency * magnitude)
+            .sum::<f32>()
+            / magnitude_sum;
+        let spread = (frequencies
+            .iter()
+            .zip(buckets.iter())
+            .map(|(frequency, magnitude)| (frequency - centroid).powi(2) * magnitude)
+            .sum::<f32>()
+            / magnitude_sum)
+            .sqrt();
+
+        let mut cumulative = 0.0;
+        let mut rolloff = frequencies.last().copied().unwrap_or(0.0);
+        for (frequency, magnitude) in frequencies.iter().zip(buckets.iter()) {
+            cumulative += magnitude * magnitude;
+            if cumulative >= ROLLOFF_FRACTION * energy {
+                rolloff = *frequency;
+                break;
+            }
+        }
+
+        // Silent buckets would pull the geometric mean to zero, so every
+        // power gets a tiny floor relative to the frame
+        let floor = energy / buckets.len() as f32 * 1e-6;
+        let log_mean =
+            buckets.iter().map(|m| (m * m + floor).ln()).sum::<f32>() / buckets.len() as f32;
+        let flatness = (log_mean.exp() / (energy / buckets.len() as f32 + floor)).clamp(0.0, 1.0);
+
+        let norm = energy.sqrt();
+        if self.previous.len() != buckets.len() {
+            self.previous.clear();
+            self.previous.resize(buckets.len(), f32::INFINITY);
+        }
+        // The previous frame is replaced by the current one while summing
+        let mut flux = 0.0;
+        for (previous, magnitude) in self.previous.iter_mut().zip(buckets.iter()) {
+            let current = magnitude / norm;
+            flux += (current - *previous).max(0.0);
+            *previous = current;
+        }
+
+        SpectralFeatures {
+            centroid,
+            spread,
+            rolloff,
+            flatness,
+            flux,
+        }
+    }
+}
+
+pub fn spectral_features_system(
+    spectrum_frames: Res<SpectrumFrames>,
+    mut extractor: ResMut<SpectralFeatureExtractor>,
+    mut features: ResMut<SpectralFeatures>,
+    config: Res<CfgResource>,
+) {
+    for frame in spectrum_frames.iter() {
+        *features = extractor.process(&frame.mono, config.0.frequency_min, config.0.frequency_max);
+    }
+}
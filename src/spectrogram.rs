@@ -0,0 +1,139 @@
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use std::time::Instant;
+
+use crate::audio_processing::{smoothing_coefficient, SpectrumFrames};
+use crate::cfg::SpectrogramOrientation;
+use crate::materials::SpectrogramMaterial;
+use crate::visualization::VisualizationType;
+use crate::CfgResource;
+
+pub const MIN_SPECTROGRAM_HISTORY: usize = 64;
+pub const MAX_SPECTROGRAM_HISTORY: usize = 2048;
+
+// Levels are shown in dB below a slowly released peak so quiet passages stay
+// visible without every frame being stretched to full brightness
+const DYNAMIC_RANGE_DB: f32 = 60.0;
+const PEAK_RELEASE_MS: f32 = 3000.0;
+
+/// Ring buffer of analyzed frames kept in a single-channel texture, one row
+/// per frame and one column per bucket.
+#[derive(Resource, Default)]
+pub struct SpectrogramHistory {
+    image: Option<Handle<Image>>,
+    num_buckets: usize,
+    rows: usize,
+    newest_row: usize,
+    peak: f32,
+    last_frame: Option<Instant>,
+    // Levels of the newest frame, copied into its row
+    levels: Vec<u8>,
+}
+
+impl SpectrogramHistory {
+    // Allocates a fresh black texture whenever the bucket count or history length changes
+    fn ensure_size(&mut self, num_buckets: usize, rows: usize, images: &mut Assets<Image>) {
+        if self.image.is_some() && self.num_buckets == num_buckets && self.rows == rows {
+            return;
+        }
+        if let Some(image) = self.image.take() {
+            images.remove(&image);
+        }
+
+        let image = Image::new_fill(
+            Extent3d {
+                width: num_buckets as u32,
+                height: rows as u32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0],
+            TextureFormat::R8Unorm,
+            RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+        );
+        self.image = Some(images.add(image));
+        self.num_buckets = num_buckets;
+        self.rows = rows;
+        self.newest_row = 0;
+    }
+
+    // Converts a frame to 0-255 levels relative to the running peak
+    fn update_levels(&mut self, buckets: &[f32], timestamp: Instant) {
+        let elapsed_seconds = match self.last_frame {
+            Some(last) => timestamp.saturating_duration_since(last).as_secs_f32(),
+            None => 0.0,
+        };
+        self.last_frame = Some(timestamp);
+
+        let frame_peak = buckets.iter().copied().fold(0.0, f32::max);
+        if frame_peak > self.peak {
+            self.peak = frame_peak;
+        } else {
+            self.peak +=
+                (frame_peak - self.peak) * smoothing_coefficient(PEAK_RELEASE_MS, elapsed_seconds);
+        }
+
+        let peak = self.peak;
+        self.levels.clear();
+        self.levels.extend(buckets.iter().map(|&magnitude| {
+            if magnitude <= 0.0 || peak <= 0.0 {
+                return 0;
+            }
+            let db = 20.0 * (magnitude / peak).log10();
+            ((db / DYNAMIC_RANGE_DB + 1.0).clamp(0.0, 1.0) * 255.0) as u8
+        }));
+    }
+}
+
+pub fn spectrogram_system(
+    spectrum_frames: Res<SpectrumFrames>,
+    mut history: ResMut<SpectrogramHistory>,
+    mut images: ResMut<Assets<Image>>,
+    mut spectrogram_material: ResMut<Assets<SpectrogramMaterial>>,
+    visualization_type: Res<VisualizationType>,
+    config: Res<CfgResource>,
+) {
+    if !matches!(*visualization_type, VisualizationType::Spectrogram) {
+        return;
+    }
+
+    let rows = config
+        .0
+        .spectrogram_history
+        .clamp(MIN_SPECTROGRAM_HISTORY, MAX_SPECTROGRAM_HISTORY);
+
+    for frame in spectrum_frames.iter() {
+        if frame.mono.is_empty() {
+            continue;
+        }
+
+        history.ensure_size(frame.mono.len(), rows, &mut images);
+        history.update_levels(&frame.mono, frame.timestamp);
+        let row = (history.newest_row + 1) % history.rows;
+        history.newest_row = row;
+
+        let Some(image) = history
+            .image
+            .as_ref()
+            .and_then(|handle| images.get_mut(handle))
+        else {
+            continue;
+        };
+        let levels = &history.levels;
+        let row_start = row * levels.len();
+        image.data[row_start..row_start + levels.len()].copy_from_slice(levels);
+    }
+
+    let Some(image) = history.image.clone() else {
+        return;
+    };
+    for (_, material) in spectrogram_material.iter_mut() {
+        material.history = image.clone();
+        material.newest_row = history.newest_row as u32;
+        material.vertical = match config.0.spectrogram_orientation {
+            SpectrogramOrientation::Horizontal => 0,
+            SpectrogramOrientation::Vertical => 1,
+        };
+    }
+}
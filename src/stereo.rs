@@ -0,0 +1,78 @@
+use bevy::prelude::*;
+
+use crate::audio_capture::AudioProcessedEvent;
+use crate::audio_processing::smoothing_coefficient;
+
+// Integration time of the stereo measurements, similar to a hardware correlation meter
+const STEREO_TIME_MS: f32 = 300.0;
+
+/// Stereo image of the captured audio, integrated over a few hundred milliseconds.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct StereoAnalysis {
+    /// Phase correlation from -1.0 (out of phase) over 0.0 (unrelated) to 1.0 (mono)
+    pub correlation: f32,
+    /// Power balance from -1.0 (left only) to 1.0 (right only)
+    pub balance: f32,
+    /// Share of the side signal in the total power, 0.0 for mono, 0.5 for
+    /// unrelated channels and 1.0 for channels in opposite phase
+    pub width: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct StereoAnalyzer {
+    // Smoothed mean products of the two channels
+    left_power: f32,
+    right_power: f32,
+    cross_power: f32,
+}
+
+impl StereoAnalyzer {
+    fn process(&mut self, left: &[f32], right: &[f32], sample_rate: u32) {
+        let len = left.len().min(right.len());
+        if len == 0 || sample_rate == 0 {
+            return;
+        }
+
+        let (mut left_power, mut right_power, mut cross_power) = (0.0, 0.0, 0.0);
+        for (l, r) in left.iter().zip(right.iter()) {
+            left_power += l * l;
+            right_power += r * r;
+            cross_power += l * r;
+        }
+
+        let coefficient = smoothing_coefficient(STEREO_TIME_MS, len as f32 / sample_rate as f32);
+        self.left_power += (left_power / len as f32 - self.left_power) * coefficient;
+        self.right_power += (right_power / len as f32 - self.right_power) * coefficient;
+        self.cross_power += (cross_power / len as f32 - self.cross_power) * coefficient;
+    }
+
+    fn analysis(&self) -> StereoAnalysis {
+        let total_power = self.left_power + self.right_power;
+        if total_power <= f32::EPSILON {
+            return StereoAnalysis::default();
+        }
+
+        // Mid and side as (L + R) / 2 and (L - R) / 2
+        let mid_power = (total_power + 2.0 * self.cross_power) * 0.25;
+        let side_power = (total_power - 2.0 * self.cross_power) * 0.25;
+
+        let channel_power = (self.left_power * self.right_power).sqrt();
+
+        StereoAnalysis {
+            correlation: (self.cross_power / channel_power.max(f32::EPSILON)).clamp(-1.0, 1.0),
+            balance: (self.right_power - self.left_power) / total_power,
+            width: (side_power / (mid_power + side_power).max(f32::EPSILON)).clamp(0.0, 1.0),
+        }
+    }
+}
+
+pub fn stereo_analysis_system(
+    mut sample_events: EventReader<AudioProcessedEvent>,
+    mut analyzer: ResMut<StereoAnalyzer>,
+    mut stereo: ResMut<StereoAnalysis>,
+) {
+    for event in sample_events.read() {
+        analyzer.process(&event.left, &event.right, event.sample_rate);
+    }
+    *stereo = analyzer.analysis();
+}
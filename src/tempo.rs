@@ -0,0 +1,285 @@
+use bevy::prelude::*;
+use std::mem;
+use std::time::Instant;
+
+use crate::beat_detection::{BeatEvent, OnsetBand, OnsetEvent};
+
+// The onset envelope is sampled at a fixed rate so lags map directly to tempi
+const ENVELOPE_RATE: f64 = 100.0;
+const ENVELOPE_SECONDS: usize = 8;
+const ENVELOPE_LEN: usize = ENVELOPE_SECONDS * ENVELOPE_RATE as usize;
+
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+// Center and width (in octaves) of the log-normal prior that resolves
+// half/double tempo ambiguities
+const PREFERRED_BPM: f32 = 120.0;
+const PRIOR_WIDTH_OCTAVES: f32 = 1.0;
+
+const ESTIMATE_INTERVAL_SECONDS: f64 = 0.5;
+// How much of the gap between the current and the new estimate is closed per update
+const BPM_SMOOTHING: f32 = 0.3;
+// Beats further than this fraction of a period from the prediction do not move the phase
+const PHASE_CAPTURE_WINDOW: f64 = 0.2;
+const PHASE_CORRECTION: f64 = 0.5;
+
+// Kick drums define the beat far more reliably than the hi-hats, which often
+// play off-beats, so onsets count less towards the tempo the higher their band
+fn onset_weight(band: OnsetBand) -> f32 {
+    match band {
+        OnsetBand::Low => 1.0,
+        OnsetBand::Mid => 0.75,
+        OnsetBand::High => 0.5,
+    }
+}
+
+/// Current tempo estimate. `beat_phase` runs from 0.0 on a beat to 1.0 just
+/// before the next one.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct TempoEstimate {
+    pub bpm: f32,
+    pub confidence: f32,
+    pub beat_phase: f32,
+}
+
+#[derive(Resource)]
+pub struct TempoTracker {
+    start: Instant,
+    envelope: Vec<f32>,
+    // Index of the newest envelope slot in absolute slot numbers since `start`
+    head_slot: u64,
+    last_estimate: f64,
+    // Time of a reference beat in seconds since `start`
+    beat_anchor: Option<f64>,
+    // Autocorrelation of every candidate lag, reused between estimates
+    correlations: Vec<f32>,
+}
+
+impl Default for TempoTracker {
+    fn default() -> Self {
+        TempoTracker {
+            start: Instant::now(),
+            envelope: vec![0.0; ENVELOPE_LEN],
+            head_slot: 0,
+            last_estimate: 0.0,
+            beat_anchor: None,
+            correlations: Vec::new(),
+        }
+    }
+}
+
+impl TempoTracker {
+    fn seconds_since_start(&self, instant: Instant) -> f64 {
+        instant.saturating_duration_since(self.start).as_secs_f64()
+    }
+
+    fn slot_for(&self, seconds: f64) -> u64 {
+        (seconds * ENVELOPE_RATE) as u64
+    }
+
+    // Moves the head forward, clearing the slots that fall out of the window
+    fn advance_to(&mut self, slot: u64) {
+        if slot <= self.head_slot {
+            return;
+        }
+        let steps = (slot - self.head_slot).min(ENVELOPE_LEN as u64);
+        for step in 1..=steps {
+            let index = ((self.head_slot + step) % ENVELOPE_LEN as u64) as usize;
+            self.envelope[index] = 0.0;
+        }
+        self.head_slot = slot;
+    }
+
+    fn add_onset(&mut self, seconds: f64, strength: f32) {
+        let slot = self.slot_for(seconds);
+        if slot > self.head_slot || self.head_slot - slot >= ENVELOPE_LEN as u64 {
+            return;
+        }
+        self.envelope[(slot % ENVELOPE_LEN as u64) as usize] += strength;
+    }
+
+    // Envelope value `age` slots before the head
+    fn envelope_at(&self, age: usize) -> f32 {
+        let slot = self.head_slot + ENVELOPE_LEN as u64 - age as u64;
+        self.envelope[(slot % ENVELOPE_LEN as u64) as usize]
+    }
+
+    fn autocorrelation(&self, lag: usize) -> f32 {
+        (0..ENVELOPE_LEN - lag)
+            .map(|age| self.envelope_at(age) * self.envelope_at(age + lag))
+            .sum()
+    }
+
+    // Returns the best period in slots and its normalized autocorrelation
+    fn estimate_period(&mut self) -> Option<(f32, f32)> {
+        let energy = self.autocorrelation(0);
+        if energy <= f32::EPSILON {
+            return None;
+        }
+
+        let min_lag = (60.0 * ENVELOPE_RATE as f32 / MAX_BPM).floor() as usize;
+        let max_lag = (60.0 * ENVELOPE_RATE as f32 / MIN_BPM).ceil() as usize;
+        let mut correlations = mem::take(&mut self.correlations);
+        correlations.clear();
+        correlations.extend((min_lag - 1..=max_lag + 1).map(|lag| self.autocorrelation(lag)));
+        let estimate = Self::pick_period(&correlations, min_lag, energy);
+        self.correlations = correlations;
+        estimate
+    }
+
+    // Strongest lag after weighting by the tempo prior, refined between slots
+    fn pick_period(correlations: &[f32], min_lag: usize, energy: f32) -> Option<(f32, f32)> {
+        let weighted = |offset: usize| {
+            let lag = (min_lag - 1 + offset) as f32;
+            let bpm = 60.0 * ENVELOPE_RATE as f32 / lag;
+            let octaves = (bpm / PREFERRED_BPM).log2() / PRIOR_WIDTH_OCTAVES;
+            correlations[offset] * (-0.5 * octaves * octaves).exp()
+        };
+
+        let best =
+            (1..correlations.len() - 1).max_by(|&a, &b| weighted(a).total_cmp(&weighted(b)))?;
+
+        // Parabolic interpolation around the peak for a fractional lag
+        let (previous, peak, next) = (
+            correlations[best - 1],
+            correlations[best],
+            correlations[best + 1],
+        );
+        let denominator = previous - 2.0 * peak + next;
+        let offset = if denominator.abs() > f32::EPSILON {
+            (0.5 * (previous - next) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+
+        let lag = (min_lag - 1 + best) as f32 + offset;
+        Some((lag, (peak / energy).clamp(0.0, 1.0)))
+    }
+
+    // Finds how many slots ago the most recent beat of a comb with the given
+    // period fell, by summing the envelope along every candidate offset
+    fn estimate_beat_offset(&self, period: f32) -> usize {
+        let period_slots = period.round().max(1.0) as usize;
+        let beats = ENVELOPE_LEN / period_slots;
+
+        (0..period_slots)
+            .max_by(|&a, &b| {
+                let score = |offset: usize| -> f32 {
+                    (0..beats)
+                        .map(|beat| offset + (beat as f32 * period).round() as usize)
+                        .filter(|&age| age < ENVELOPE_LEN)
+                        .map(|age| self.envelope_at(age))
+                        .sum()
+                };
+                score(a).total_cmp(&score(b))
+            })
+            .unwrap_or(0)
+    }
+}
+
+pub fn tempo_tracking_system(
+    mut onset_events: EventReader<OnsetEvent>,
+    mut beat_events: EventReader<BeatEvent>,
+    mut tracker: ResMut<TempoTracker>,
+    mut estimate: ResMut<TempoEstimate>,
+) {
+    let now = tracker.seconds_since_start(Instant::now());
+    let now_slot = tracker.slot_for(now);
+    tracker.advance_to(now_slot);
+
+    for onset in onset_events.read() {
+        let seconds = tracker.seconds_since_start(onset.timestamp);
+        tracker.add_onset(seconds, onset.strength * onset_weight(onset.band));
+    }
+
+    if now - tracker.last_estimate >= ESTIMATE_INTERVAL_SECONDS {
+        tracker.last_estimate = now;
+
+        if let Some((lag, confidence)) = tracker.estimate_period() {
+            let bpm = 60.0 * ENVELOPE_RATE as f32 / lag;
+            estimate.bpm = if estimate.bpm > 0.0 {
+                estimate.bpm + (bpm - estimate.bpm) * BPM_SMOOTHING
+            } else {
+                bpm
+            };
+            estimate.confidence = confidence;
+
+            let offset = tracker.estimate_beat_offset(60.0 * ENVELOPE_RATE as f32 / estimate.bpm);
+            tracker.beat_anchor =
+                Some(now_slot as f64 / ENVELOPE_RATE - offset as f64 / ENVELOPE_RATE);
+        } else {
+            estimate.confidence = 0.0;
+        }
+    }
+
+    if estimate.bpm <= 0.0 {
+        beat_events.clear();
+        return;
+    }
+    let period = 60.0 / estimate.bpm as f64;
+
+    // Pull the phase towards detected beats that land close to the prediction,
+    // the harder the more clearly they crossed the threshold. Beats carried by
+    // the treble are too often off-beats to be trusted.
+    for beat in beat_events.read() {
+        if beat.band == OnsetBand::High {
+            continue;
+        }
+        let seconds = tracker.seconds_since_start(beat.timestamp);
+        match tracker.beat_anchor {
+            Some(anchor) => {
+                let error = ((seconds - anchor) / period + 0.5).rem_euclid(1.0) - 0.5;
+                if error.abs() < PHASE_CAPTURE_WINDOW {
+                    // The strength is above 1.0, so the weight stays below 1.0
+                    let weight = 1.0 - 1.0 / beat.strength.max(1.0) as f64;
+                    tracker.beat_anchor = Some(anchor + error * period * PHASE_CORRECTION * weight);
+                }
+            }
+            None => tracker.beat_anchor = Some(seconds),
+        }
+    }
+
+    if let Some(anchor) = tracker.beat_anchor {
+        estimate.beat_phase = ((now - anchor) / period).rem_euclid(1.0) as f32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Clicks of equal strength over the whole envelope, leaving the head
+    // `trailing_slots` after the last one
+    fn click_track(bpm: f64, trailing_slots: u64) -> TempoTracker {
+        let mut tracker = TempoTracker::default();
+        let period = 60.0 / bpm;
+        let clicks = (ENVELOPE_SECONDS as f64 / period) as usize;
+        for click in 0..clicks {
+            let seconds = click as f64 * period;
+            tracker.advance_to(tracker.slot_for(seconds));
+            tracker.add_onset(seconds, 1.0);
+        }
+        tracker.advance_to(tracker.head_slot + trailing_slots);
+        tracker
+    }
+
+    #[test]
+    fn click_track_tempo_and_phase() {
+        for bpm in [72.0, 95.0, 128.0, 150.0] {
+            let mut tracker = click_track(bpm, 10);
+            let (lag, confidence) = tracker.estimate_period().unwrap();
+            let estimate = 60.0 * ENVELOPE_RATE / lag as f64;
+            assert!(
+                (estimate - bpm).abs() < 1.5,
+                "{} BPM estimated as {}",
+                bpm,
+                estimate
+            );
+            assert!(confidence > 0.5, "{} BPM confidence {}", bpm, confidence);
+
+            // The comb lines up with the last click
+            let offset = tracker.estimate_beat_offset(lag);
+            assert!(offset.abs_diff(10) <= 1, "{} BPM offset {}", bpm, offset);
+        }
+    }
+}
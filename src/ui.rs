@@ -5,9 +5,26 @@ use crate::CircleSplitMaterial;
 use crate::PolygonMaterial;
 use crate::WaveMaterial;
 
+use crate::band_energy::{BandEnergies, MAX_SHADER_BANDS};
+use crate::cfg::{
+    AnalyzerMode, ChannelSource, EnergyBand, NoiseFloorMode, SpectrogramOrientation, StageKind,
+    TiltPreset, TriggerMode, TwoChannelMode, VectorscopeMode, WeightingCurve,
+};
+use crate::hpss::{MAX_HPSS_FRAMES, MAX_HPSS_KERNEL, MIN_HPSS_FRAMES, MIN_HPSS_KERNEL};
+use crate::multi_resolution::{MAX_FFT_SIZE, MIN_FFT_SIZE};
+use crate::pipeline::{is_required, stage_phase};
+use crate::spectrogram::{MAX_SPECTROGRAM_HISTORY, MIN_SPECTROGRAM_HISTORY};
+use crate::visualization::ExtraVisualizers;
+use crate::waveform::{MAX_WAVEFORM_POINTS, MAX_WAVEFORM_WINDOW_MS, MIN_WAVEFORM_POINTS};
+use crate::weighting::DEFAULT_TILT_PIVOT_HZ;
 use crate::CfgResource;
+use crate::Chroma;
 use crate::GUIToggle;
+use crate::StereoAnalysis;
 use crate::StringMaterial;
+use crate::TempoEstimate;
+use crate::{Loudness, LoudnessMeter};
+use crate::{MAX_BUCKETS, MIN_BUCKETS};
 //use bevy::math::Vec4Swizzles;
 use bevy_egui::{egui, EguiContexts};
 
@@ -41,7 +58,7 @@ pub struct UIPlugin;
 
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, uniform_update_ui_system);
+        app.add_systems(Update, (uniform_update_ui_system, loudness_overlay_system));
     }
 }
 
@@ -79,6 +96,182 @@ impl Default for Colors {
     }
 }
 
+fn channel_source_name(source: ChannelSource) -> &'static str {
+    match source {
+        ChannelSource::Left => "Left",
+        ChannelSource::Right => "Right",
+        ChannelSource::Mono => "Mono",
+        ChannelSource::Mid => "Mid",
+        ChannelSource::Side => "Side",
+        ChannelSource::Max => "Max",
+    }
+}
+
+fn channel_source_selector(ui: &mut egui::Ui, label: &str, source: &mut ChannelSource) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        egui::ComboBox::from_id_source(label)
+            .selected_text(channel_source_name(*source))
+            .show_ui(ui, |ui| {
+                for option in ChannelSource::ALL {
+                    ui.selectable_value(source, option, channel_source_name(option));
+                }
+            });
+    });
+}
+
+fn fft_size_selector(ui: &mut egui::Ui, label: &str, size: &mut usize) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        egui::ComboBox::from_id_source(label)
+            .selected_text(size.to_string())
+            .show_ui(ui, |ui| {
+                let mut option = MIN_FFT_SIZE;
+                while option <= MAX_FFT_SIZE {
+                    ui.selectable_value(size, option, option.to_string());
+                    option *= 2;
+                }
+            });
+    });
+}
+
+fn weighting_curve_name(curve: WeightingCurve) -> &'static str {
+    match curve {
+        WeightingCurve::Flat => "Flat",
+        WeightingCurve::A => "A",
+        WeightingCurve::C => "C",
+        WeightingCurve::K => "K",
+        WeightingCurve::Itu468 => "ITU-R 468",
+    }
+}
+
+fn stage_name(kind: StageKind) -> &'static str {
+    match kind {
+        StageKind::Window => "Window",
+        StageKind::Fft => "FFT",
+        StageKind::Bucketize => "Bucketize",
+        StageKind::Weighting => "Weighting",
+        StageKind::Smoothing => "Smoothing",
+        StageKind::NoiseFloor => "Noise Floor",
+        StageKind::Animate => "Animate",
+        StageKind::Normalize => "Normalize",
+    }
+}
+
+// Lists the processing stages in order with controls to move, remove and add
+// them. Stages only move within their phase, and the required ones stay.
+fn pipeline_editor(ui: &mut egui::Ui, pipeline: &mut Vec<StageKind>) {
+    ui.label("Pipeline:");
+    let mut move_up = None;
+    let mut remove = None;
+    for (i, &kind) in pipeline.iter().enumerate() {
+        ui.horizontal(|ui| {
+            ui.label(format!("{}. {}", i + 1, stage_name(kind)));
+            let can_move = i > 0 && stage_phase(pipeline[i - 1]) == stage_phase(kind);
+            if ui
+                .add_enabled(can_move, egui::Button::new("Up").small())
+                .clicked()
+            {
+                move_up = Some(i);
+            }
+            if ui
+                .add_enabled(!is_required(kind), egui::Button::new("Remove").small())
+                .clicked()
+            {
+                remove = Some(i);
+            }
+        });
+    }
+    if let Some(i) = move_up {
+        pipeline.swap(i - 1, i);
+    }
+    if let Some(i) = remove {
+        pipeline.remove(i);
+    }
+
+    let missing: Vec<StageKind> = StageKind::ALL
+        .into_iter()
+        .filter(|kind| !pipeline.contains(kind))
+        .collect();
+    if !missing.is_empty() {
+        egui::ComboBox::from_id_source("Add Stage")
+            .selected_text("Add Stage")
+            .show_ui(ui, |ui| {
+                for kind in missing {
+                    if ui.selectable_label(false, stage_name(kind)).clicked() {
+                        // Added back at the end of its phase
+                        let position = pipeline
+                            .iter()
+                            .rposition(|&other| stage_phase(other) <= stage_phase(kind))
+                            .map_or(0, |i| i + 1);
+                        pipeline.insert(position, kind);
+                    }
+                }
+            });
+    }
+}
+
+// Edits the energy bands in place, each with its current level
+fn band_editor(ui: &mut egui::Ui, bands: &mut Vec<EnergyBand>, levels: &[f32]) {
+    ui.label("Energy Bands:");
+    let mut remove = None;
+    for (i, band) in bands.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut band.name).desired_width(60.0));
+            ui.add(
+                egui::DragValue::new(&mut band.frequency_min)
+                    .clamp_range(1.0..=24_000.0)
+                    .suffix(" Hz"),
+            );
+            ui.label("to");
+            ui.add(
+                egui::DragValue::new(&mut band.frequency_max)
+                    .clamp_range(1.0..=24_000.0)
+                    .suffix(" Hz"),
+            );
+            ui.add(
+                egui::DragValue::new(&mut band.attack_ms)
+                    .clamp_range(0.0..=1000.0)
+                    .prefix("Attack: ")
+                    .suffix(" ms"),
+            );
+            ui.add(
+                egui::DragValue::new(&mut band.release_ms)
+                    .clamp_range(0.0..=2000.0)
+                    .prefix("Release: ")
+                    .suffix(" ms"),
+            );
+            ui.add(
+                egui::ProgressBar::new(levels.get(i).copied().unwrap_or(0.0)).desired_width(60.0),
+            );
+            if ui.small_button("Remove").clicked() {
+                remove = Some(i);
+            }
+        });
+    }
+    if let Some(i) = remove {
+        bands.remove(i);
+    }
+
+    // Only the first bands fit into the shader uniform
+    if ui
+        .add_enabled(
+            bands.len() < MAX_SHADER_BANDS,
+            egui::Button::new("Add Band").small(),
+        )
+        .clicked()
+    {
+        let frequency_min = bands.last().map_or(20.0, |band| band.frequency_max);
+        bands.push(EnergyBand {
+            name: format!("Band {}", bands.len() + 1),
+            frequency_min,
+            frequency_max: frequency_min * 2.0,
+            attack_ms: 10.0,
+            release_ms: 200.0,
+        });
+    }
+}
+
 fn uniform_update_ui_system(
     mut ctx: EguiContexts,
     mut bar_material: ResMut<Assets<BarMaterial>>,
@@ -86,9 +279,15 @@ fn uniform_update_ui_system(
     mut string_material: ResMut<Assets<StringMaterial>>,
     mut wave_material: ResMut<Assets<WaveMaterial>>,
     mut polygon_material: ResMut<Assets<PolygonMaterial>>,
+    mut extra_visualizers: ExtraVisualizers,
     mut colors: ResMut<Colors>, // Added the Colors resource
     mut config: ResMut<CfgResource>,
     toggle: Res<GUIToggle>,
+    tempo: Res<TempoEstimate>,
+    chroma: Res<Chroma>,
+    mut loudness_meter: ResMut<LoudnessMeter>,
+    stereo: Res<StereoAnalysis>,
+    bands: Res<BandEnergies>,
 ) {
     // If the toggle is not active, return early
     if !toggle.active {
@@ -97,12 +296,151 @@ fn uniform_update_ui_system(
     let context = ctx.ctx_mut();
     egui::Window::new("Update Uniforms").show(context, |ui| {
         ui.horizontal(|ui| {
-            ui.label("Interpolation Factor:");
+            ui.label(format!(
+                "Tempo: {:.1} BPM ({:.0}% confidence)",
+                tempo.bpm,
+                tempo.confidence * 100.0
+            ));
+            ui.add(egui::ProgressBar::new(tempo.beat_phase).desired_width(60.0));
+        });
+        ui.horizontal(|ui| match chroma.key {
+            Some(key) => ui.label(format!(
+                "Key: {} ({:.0}% confidence)",
+                key.name(),
+                key.confidence * 100.0
+            )),
+            None => ui.label("Key: unknown"),
+        });
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "Correlation: {:+.2}  Balance: {:+.2}  Width: {:.2}",
+                stereo.correlation, stereo.balance, stereo.width
+            ));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Level Meters:");
+            ui.checkbox(&mut config.0.show_meters, "");
+            if ui.button("Reset Loudness").clicked() {
+                loudness_meter.reset();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Attack (ms):");
+            ui.add(egui::Slider::new(&mut config.0.attack_ms, 0.0..=1000.0).logarithmic(true));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Release (ms):");
+            ui.add(egui::Slider::new(&mut config.0.release_ms, 0.0..=2000.0).logarithmic(true));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Analyzer:");
+            ui.radio_value(&mut config.0.analyzer, AnalyzerMode::Fft, "FFT");
+            ui.radio_value(
+                &mut config.0.analyzer,
+                AnalyzerMode::ConstantQ,
+                "Constant-Q",
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Multi-Resolution FFT:");
+            ui.checkbox(&mut config.0.multi_resolution, "");
+        });
+        fft_size_selector(ui, "FFT Size:", &mut config.0.fft_size);
+        fft_size_selector(ui, "Long FFT:", &mut config.0.long_fft_size);
+        fft_size_selector(ui, "Short FFT:", &mut config.0.short_fft_size);
+        ui.horizontal(|ui| {
+            ui.label("Crossover Start (Hz):");
+            ui.add(egui::Slider::new(&mut config.0.crossover_min, 20.0..=5000.0).logarithmic(true));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Crossover End (Hz):");
+            ui.add(egui::Slider::new(&mut config.0.crossover_max, 20.0..=5000.0).logarithmic(true));
+        });
+        pipeline_editor(ui, &mut config.0.pipeline);
+        ui.horizontal(|ui| {
+            ui.label("Channels:");
+            ui.radio_value(
+                &mut config.0.two_channel_mode,
+                TwoChannelMode::LeftRight,
+                "Left/Right",
+            );
+            ui.radio_value(
+                &mut config.0.two_channel_mode,
+                TwoChannelMode::MidSide,
+                "Mid/Side",
+            );
+            ui.radio_value(
+                &mut config.0.two_channel_mode,
+                TwoChannelMode::HarmonicPercussive,
+                "Harmonic/Percussive",
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("HPSS History (frames):");
+            ui.add(egui::Slider::new(
+                &mut config.0.hpss_frames,
+                MIN_HPSS_FRAMES..=MAX_HPSS_FRAMES,
+            ));
+        });
+        ui.horizontal(|ui| {
+            ui.label("HPSS Kernel (bins):");
             ui.add(egui::Slider::new(
-                &mut config.0.interpolation_factor,
-                0.0..=1.0,
+                &mut config.0.hpss_kernel,
+                MIN_HPSS_KERNEL..=MAX_HPSS_KERNEL,
             ));
         });
+        ui.horizontal(|ui| {
+            ui.label("Weighting:");
+            egui::ComboBox::from_id_source("Weighting:")
+                .selected_text(weighting_curve_name(config.0.weighting))
+                .show_ui(ui, |ui| {
+                    for curve in WeightingCurve::ALL {
+                        ui.selectable_value(
+                            &mut config.0.weighting,
+                            curve,
+                            weighting_curve_name(curve),
+                        );
+                    }
+                });
+        });
+        ui.horizontal(|ui| {
+            ui.label("Tilt Preset:");
+            ui.selectable_value(&mut config.0.tilt_preset, TiltPreset::Off, "Off");
+            if ui
+                .selectable_value(
+                    &mut config.0.tilt_preset,
+                    TiltPreset::PinkNoiseFlat,
+                    "Pink Noise Flat",
+                )
+                .clicked()
+            {
+                config.0.tilt_pivot_hz = DEFAULT_TILT_PIVOT_HZ;
+            }
+            ui.selectable_value(&mut config.0.tilt_preset, TiltPreset::Custom, "Custom");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Tilt (dB/oct):");
+            // The pink noise tilt depends on the analyzer and is not edited
+            ui.add_enabled(
+                config.0.tilt_preset == TiltPreset::Custom,
+                egui::Slider::new(&mut config.0.weighting_tilt, -6.0..=6.0),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Tilt Pivot (Hz):");
+            ui.add(
+                egui::Slider::new(&mut config.0.tilt_pivot_hz, 20.0..=20_000.0).logarithmic(true),
+            );
+        });
+        channel_source_selector(ui, "Bar Channel:", &mut config.0.bar_channel);
+        channel_source_selector(ui, "Polygon Channel:", &mut config.0.polygon_channel);
+        ui.horizontal(|ui| {
+            ui.label("Buckets:");
+            ui.add(
+                egui::Slider::new(&mut config.0.num_buckets, MIN_BUCKETS..=MAX_BUCKETS)
+                    .logarithmic(true),
+            );
+        });
         ui.horizontal(|ui| {
             ui.label("Smoothing:");
             ui.add(egui::Slider::new(&mut config.0.smoothing, 0..=10));
@@ -115,6 +453,122 @@ fn uniform_update_ui_system(
             ui.label("Gate Threshold:");
             ui.add(egui::Slider::new(&mut config.0.gate_threshold, 0.0..=10.0));
         });
+        ui.horizontal(|ui| {
+            ui.label("Noise Floor:");
+            ui.radio_value(
+                &mut config.0.noise_floor_mode,
+                NoiseFloorMode::Subtract,
+                "Subtract",
+            );
+            ui.radio_value(&mut config.0.noise_floor_mode, NoiseFloorMode::Gate, "Gate");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Gate Hold (ms):");
+            ui.add(egui::Slider::new(&mut config.0.gate_hold_ms, 0.0..=1000.0));
+        });
+        band_editor(ui, &mut config.0.energy_bands, &bands.levels);
+        ui.horizontal(|ui| {
+            ui.label("Onset Sensitivity:");
+            ui.add(egui::Slider::new(
+                &mut config.0.onset_sensitivity,
+                0.0..=5.0,
+            ));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Min Beat Interval (ms):");
+            ui.add(egui::Slider::new(
+                &mut config.0.beat_min_interval_ms,
+                0.0..=1000.0,
+            ));
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Time Scale (ms):");
+            ui.add(
+                egui::Slider::new(
+                    &mut config.0.waveform_window_ms,
+                    1.0..=MAX_WAVEFORM_WINDOW_MS,
+                )
+                .logarithmic(true),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Waveform Points:");
+            ui.add(
+                egui::Slider::new(
+                    &mut config.0.waveform_points,
+                    MIN_WAVEFORM_POINTS..=MAX_WAVEFORM_POINTS,
+                )
+                .logarithmic(true),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Trigger:");
+            ui.radio_value(&mut config.0.trigger_mode, TriggerMode::Free, "Free");
+            ui.radio_value(
+                &mut config.0.trigger_mode,
+                TriggerMode::RisingEdge,
+                "Rising Edge",
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Trigger Level:");
+            ui.add(egui::Slider::new(&mut config.0.trigger_level, -1.0..=1.0));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Line Width (px):");
+            ui.add(egui::Slider::new(
+                &mut config.0.scope_line_width,
+                0.5..=10.0,
+            ));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Glow:");
+            ui.add(egui::Slider::new(&mut config.0.scope_glow, 0.0..=1.0));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Spectrogram History:");
+            ui.add(
+                egui::Slider::new(
+                    &mut config.0.spectrogram_history,
+                    MIN_SPECTROGRAM_HISTORY..=MAX_SPECTROGRAM_HISTORY,
+                )
+                .logarithmic(true),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Spectrogram Scroll:");
+            ui.radio_value(
+                &mut config.0.spectrogram_orientation,
+                SpectrogramOrientation::Horizontal,
+                "Horizontal",
+            );
+            ui.radio_value(
+                &mut config.0.spectrogram_orientation,
+                SpectrogramOrientation::Vertical,
+                "Vertical",
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Vectorscope:");
+            ui.radio_value(
+                &mut config.0.vectorscope_mode,
+                VectorscopeMode::LeftRight,
+                "Lissajous (L/R)",
+            );
+            ui.radio_value(
+                &mut config.0.vectorscope_mode,
+                VectorscopeMode::MidSide,
+                "Goniometer (M/S)",
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Persistence (ms):");
+            ui.add(
+                egui::Slider::new(&mut config.0.vectorscope_persistence_ms, 0.0..=2000.0)
+                    .logarithmic(true),
+            );
+        });
 
         // Determine the adjusted minimum and maximum values for the sliders
         let adjusted_freq_min_max = if config.0.frequency_min + 512.0 > config.0.frequency_max {
@@ -232,5 +686,62 @@ fn uniform_update_ui_system(
         if let Some(material) = polygon_material.iter_mut().next() {
             update_material!(material, colors);
         }
+        if let Some(material) = extra_visualizers.chroma_material.iter_mut().next() {
+            update_material!(material, colors);
+        }
+        if let Some(material) = extra_visualizers.oscilloscope_material.iter_mut().next() {
+            update_material!(material, colors);
+        }
+        if let Some(material) = extra_visualizers.spectrogram_material.iter_mut().next() {
+            update_material!(material, colors);
+        }
+        if let Some(material) = extra_visualizers.vectorscope_material.iter_mut().next() {
+            update_material!(material, colors);
+        }
     });
 }
+
+// Meter scale in dB, the bars are empty at the bottom and full at 0 dB
+const METER_FLOOR_DB: f32 = -60.0;
+
+fn meter_bar(ui: &mut egui::Ui, label: &str, value_db: f32, unit: &str) {
+    let fraction = if value_db.is_finite() {
+        (1.0 - value_db / METER_FLOOR_DB).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let value = if value_db.is_finite() {
+        format!("{:.1}", value_db)
+    } else {
+        "-inf".to_string()
+    };
+    ui.add(
+        egui::ProgressBar::new(fraction)
+            .desired_width(220.0)
+            .text(format!("{}: {} {}", label, value, unit)),
+    );
+}
+
+fn loudness_overlay_system(
+    mut ctx: EguiContexts,
+    config: Res<CfgResource>,
+    loudness: Res<Loudness>,
+) {
+    if !config.0.show_meters {
+        return;
+    }
+    let context = ctx.ctx_mut();
+    egui::Area::new(egui::Id::new("loudness_meters"))
+        .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+        .show(context, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                meter_bar(ui, "Momentary", loudness.momentary_lufs, "LUFS");
+                meter_bar(ui, "Short-term", loudness.short_term_lufs, "LUFS");
+                meter_bar(ui, "Integrated", loudness.integrated_lufs, "LUFS");
+                meter_bar(ui, "RMS L", loudness.rms_db[0], "dBFS");
+                meter_bar(ui, "RMS R", loudness.rms_db[1], "dBFS");
+                meter_bar(ui, "True Peak L", loudness.true_peak_db[0], "dBTP");
+                meter_bar(ui, "True Peak R", loudness.true_peak_db[1], "dBTP");
+            });
+        });
+}
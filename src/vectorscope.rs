@@ -0,0 +1,117 @@
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::audio_capture::AudioProcessedEvent;
+use crate::cfg::VectorscopeMode;
+use crate::materials::VectorscopeMaterial;
+use crate::visualization::VisualizationType;
+use crate::CfgResource;
+
+// Resolution of the square trace texture
+const TRACE_SIZE: usize = 512;
+// Brightness added by every sample pair that lands on a pixel
+const HIT_INTENSITY: f32 = 0.25;
+// Full scale is drawn slightly inside the edge so peaks stay visible
+const SCALE: f32 = 0.95;
+
+/// Phosphor-like trace of the stereo sample pairs. Every pixel fades with the
+/// configured persistence and is re-lit by the samples that hit it.
+#[derive(Resource)]
+pub struct VectorscopeTrace {
+    image: Option<Handle<Image>>,
+    intensity: Vec<f32>,
+}
+
+impl Default for VectorscopeTrace {
+    fn default() -> Self {
+        VectorscopeTrace {
+            image: None,
+            intensity: vec![0.0; TRACE_SIZE * TRACE_SIZE],
+        }
+    }
+}
+
+impl VectorscopeTrace {
+    fn image(&mut self, images: &mut Assets<Image>) -> Handle<Image> {
+        self.image
+            .get_or_insert_with(|| {
+                images.add(Image::new_fill(
+                    Extent3d {
+                        width: TRACE_SIZE as u32,
+                        height: TRACE_SIZE as u32,
+                        depth_or_array_layers: 1,
+                    },
+                    TextureDimension::D2,
+                    &[0],
+                    TextureFormat::R8Unorm,
+                    RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+                ))
+            })
+            .clone()
+    }
+
+    fn fade(&mut self, elapsed_seconds: f32, persistence_ms: f32) {
+        let factor = if persistence_ms > 0.0 {
+            (-elapsed_seconds * 1000.0 / persistence_ms).exp()
+        } else {
+            0.0
+        };
+        for value in self.intensity.iter_mut() {
+            *value *= factor;
+        }
+    }
+
+    fn plot(&mut self, left: &[f32], right: &[f32], mode: VectorscopeMode) {
+        for (&l, &r) in left.iter().zip(right.iter()) {
+            // Goniometer mode turns the display by 45 degrees so mono is
+            // vertical and out of phase content is horizontal. Mid and side
+            // are halved like everywhere else, so full-scale mono still fits.
+            let (x, y) = match mode {
+                VectorscopeMode::LeftRight => (l, r),
+                VectorscopeMode::MidSide => ((r - l) * 0.5, (l + r) * 0.5),
+            };
+
+            let to_pixel = |value: f32| {
+                (((value * SCALE).clamp(-1.0, 1.0) * 0.5 + 0.5) * (TRACE_SIZE - 1) as f32).round()
+                    as usize
+            };
+            // Texture rows run from top to bottom
+            let column = to_pixel(x);
+            let row = TRACE_SIZE - 1 - to_pixel(y);
+            let pixel = &mut self.intensity[row * TRACE_SIZE + column];
+            *pixel = (*pixel + HIT_INTENSITY).min(1.0);
+        }
+    }
+}
+
+pub fn vectorscope_system(
+    mut sample_events: EventReader<AudioProcessedEvent>,
+    mut trace: ResMut<VectorscopeTrace>,
+    mut images: ResMut<Assets<Image>>,
+    mut vectorscope_material: ResMut<Assets<VectorscopeMaterial>>,
+    visualization_type: Res<VisualizationType>,
+    config: Res<CfgResource>,
+    time: Res<Time>,
+) {
+    if !matches!(*visualization_type, VisualizationType::Vectorscope) {
+        sample_events.clear();
+        return;
+    }
+
+    trace.fade(time.delta_seconds(), config.0.vectorscope_persistence_ms);
+    for event in sample_events.read() {
+        trace.plot(&event.left, &event.right, config.0.vectorscope_mode);
+    }
+
+    let handle = trace.image(&mut images);
+    if let Some(image) = images.get_mut(&handle) {
+        for (texel, value) in image.data.iter_mut().zip(trace.intensity.iter()) {
+            *texel = (value * 255.0) as u8;
+        }
+    }
+
+    for (_, material) in vectorscope_material.iter_mut() {
+        material.trace = handle.clone();
+    }
+}
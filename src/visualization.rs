@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use bevy::ecs::system::SystemParam;
 use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
 use bevy::window::{PrimaryWindow, WindowResized};
 use bevy::math::primitives::Rectangle;
@@ -6,6 +7,8 @@ use bevy::math::primitives::Rectangle;
 use crate::audio_capture::AudioThreadFlag;
 use crate::audio_capture::{stream_input, DeviceType};
 use crate::materials::*;
+use crate::band_energy::BandEnergies;
+use crate::spectral_features::SpectralFeatures;
 use crate::AudioReceiver;
 use crate::CfgResource;
 use crate::Colors;
@@ -13,7 +16,10 @@ use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 
-use crate::{impl_material_new, impl_one_channel_material_new, prepare_material};
+use crate::{
+    impl_material_new, impl_one_channel_material_new, impl_visualizer_material_new,
+    prepare_material,
+};
 
 // Move material implementations to module level
 impl_material_new!(StringMaterial);
@@ -22,6 +28,63 @@ impl_material_new!(WaveMaterial);
 impl_one_channel_material_new!(BarMaterial);
 impl_one_channel_material_new!(PolygonMaterial);
 
+impl_visualizer_material_new!(
+    OscilloscopeMaterial,
+    // Storage buffers cannot be empty, the real data arrives with the first captured buffer
+    left_data: vec![0.0],
+    right_data: vec![0.0],
+    beat_phase: 0.0,
+    line_width: 2.0,
+    glow: 0.5,
+);
+
+impl_visualizer_material_new!(
+    SpectrogramMaterial,
+    // Replaced by the history texture once the first frame is analyzed
+    history: Handle::default(),
+    newest_row: 0,
+    vertical: 0,
+);
+
+impl_visualizer_material_new!(
+    VectorscopeMaterial,
+    // Replaced by the trace texture once the first samples are plotted
+    trace: Handle::default(),
+);
+
+impl_visualizer_material_new!(
+    ChromaMaterial,
+    chroma: [Vec4::ZERO; 3],
+    beat_phase: 0.0,
+    key_tonic: -1,
+    key_minor: 0,
+);
+
+// Materials and entities of the visualizers fed by their own systems, grouped
+// to keep the spawning and GUI systems within Bevy's parameter limit
+#[derive(SystemParam)]
+pub struct ExtraVisualizers<'w> {
+    pub chroma_material: ResMut<'w, Assets<ChromaMaterial>>,
+    pub chroma_entity: ResMut<'w, ChromaEntity>,
+    pub oscilloscope_material: ResMut<'w, Assets<OscilloscopeMaterial>>,
+    pub oscilloscope_entity: ResMut<'w, OscilloscopeEntity>,
+    pub spectrogram_material: ResMut<'w, Assets<SpectrogramMaterial>>,
+    pub spectrogram_entity: ResMut<'w, SpectrogramEntity>,
+    pub vectorscope_material: ResMut<'w, Assets<VectorscopeMaterial>>,
+    pub vectorscope_entity: ResMut<'w, VectorscopeEntity>,
+}
+
+// Materials of the visualizers fed by the audio event system, grouped like
+// `ExtraVisualizers` for the systems passing uniforms to the materials
+#[derive(SystemParam)]
+pub struct VisualizerMaterials<'w> {
+    pub bar_material: ResMut<'w, Assets<BarMaterial>>,
+    pub string_material: ResMut<'w, Assets<StringMaterial>>,
+    pub circle_split_material: ResMut<'w, Assets<CircleSplitMaterial>>,
+    pub polygon_material: ResMut<'w, Assets<PolygonMaterial>>,
+    pub wave_material: ResMut<'w, Assets<WaveMaterial>>,
+}
+
 #[derive(Resource)]
 #[derive(Default)]
 pub enum VisualizationType {
@@ -31,6 +94,10 @@ pub enum VisualizationType {
     CircleSplit,
     Polygon,
     Wave,
+    Chroma,
+    Oscilloscope,
+    Spectrogram,
+    Vectorscope,
 }
 
 // visualization_toggle_system now ensures proper cleanup and restart
@@ -65,7 +132,11 @@ pub fn visualization_toggle_system(
             VisualizationType::String => VisualizationType::CircleSplit,
             VisualizationType::CircleSplit => VisualizationType::Wave,
             VisualizationType::Wave => VisualizationType::Polygon,
-            VisualizationType::Polygon => VisualizationType::Bar,
+            VisualizationType::Polygon => VisualizationType::Chroma,
+            VisualizationType::Chroma => VisualizationType::Oscilloscope,
+            VisualizationType::Oscilloscope => VisualizationType::Spectrogram,
+            VisualizationType::Spectrogram => VisualizationType::Vectorscope,
+            VisualizationType::Vectorscope => VisualizationType::Bar,
         };
 
         // Restart the audio thread with a new run flag
@@ -95,6 +166,16 @@ pub fn spawn_visualization(
     mut polygon_entity: ResMut<PolygonEntity>,
     mut wave_material: ResMut<Assets<WaveMaterial>>,
     mut wave_entity: ResMut<WaveEntity>,
+    ExtraVisualizers {
+        mut chroma_material,
+        mut chroma_entity,
+        mut oscilloscope_material,
+        mut oscilloscope_entity,
+        mut spectrogram_material,
+        mut spectrogram_entity,
+        mut vectorscope_material,
+        mut vectorscope_entity,
+    }: ExtraVisualizers,
     visualization_type: Res<VisualizationType>,
     primary_window: Query<&Window, With<PrimaryWindow>>,
     colors: Res<Colors>,
@@ -128,6 +209,18 @@ pub fn spawn_visualization(
         if let Some(entity) = wave_entity.0.take() {
             commands.entity(entity).despawn();
         }
+        if let Some(entity) = chroma_entity.0.take() {
+            commands.entity(entity).despawn();
+        }
+        if let Some(entity) = oscilloscope_entity.0.take() {
+            commands.entity(entity).despawn();
+        }
+        if let Some(entity) = spectrogram_entity.0.take() {
+            commands.entity(entity).despawn();
+        }
+        if let Some(entity) = vectorscope_entity.0.take() {
+            commands.entity(entity).despawn();
+        }
 
         match *visualization_type {
             VisualizationType::Bar => {
@@ -227,6 +320,82 @@ pub fn spawn_visualization(
                         .id(),
                 );
             }
+            VisualizationType::Chroma => {
+                let chroma_material_handle = prepare_material!(
+                    ChromaMaterial,
+                    chroma_material,
+                    window_size.x,
+                    window_size.y,
+                    colors
+                );
+                chroma_entity.0 = Some(
+                    commands
+                        .spawn(MaterialMesh2dBundle {
+                            mesh: audio_mesh.clone(),
+                            material: chroma_material_handle,
+                            transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                            ..Default::default()
+                        })
+                        .id(),
+                );
+            }
+            VisualizationType::Oscilloscope => {
+                let oscilloscope_material_handle = prepare_material!(
+                    OscilloscopeMaterial,
+                    oscilloscope_material,
+                    window_size.x,
+                    window_size.y,
+                    colors
+                );
+                oscilloscope_entity.0 = Some(
+                    commands
+                        .spawn(MaterialMesh2dBundle {
+                            mesh: audio_mesh.clone(),
+                            material: oscilloscope_material_handle,
+                            transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                            ..Default::default()
+                        })
+                        .id(),
+                );
+            }
+            VisualizationType::Spectrogram => {
+                let spectrogram_material_handle = prepare_material!(
+                    SpectrogramMaterial,
+                    spectrogram_material,
+                    window_size.x,
+                    window_size.y,
+                    colors
+                );
+                spectrogram_entity.0 = Some(
+                    commands
+                        .spawn(MaterialMesh2dBundle {
+                            mesh: audio_mesh.clone(),
+                            material: spectrogram_material_handle,
+                            transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                            ..Default::default()
+                        })
+                        .id(),
+                );
+            }
+            VisualizationType::Vectorscope => {
+                let vectorscope_material_handle = prepare_material!(
+                    VectorscopeMaterial,
+                    vectorscope_material,
+                    window_size.x,
+                    window_size.y,
+                    colors
+                );
+                vectorscope_entity.0 = Some(
+                    commands
+                        .spawn(MaterialMesh2dBundle {
+                            mesh: audio_mesh.clone(),
+                            material: vectorscope_material_handle,
+                            transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                            ..Default::default()
+                        })
+                        .id(),
+                );
+            }
         }
 
         println!("Spawned Audio Visualization");
@@ -248,6 +417,16 @@ pub fn window_resized_event(
     mut polygon_entity: ResMut<PolygonEntity>,
     mut wave_material: ResMut<Assets<WaveMaterial>>,
     mut wave_entity: ResMut<WaveEntity>,
+    ExtraVisualizers {
+        mut chroma_material,
+        mut chroma_entity,
+        mut oscilloscope_material,
+        mut oscilloscope_entity,
+        mut spectrogram_material,
+        mut spectrogram_entity,
+        mut vectorscope_material,
+        mut vectorscope_entity,
+    }: ExtraVisualizers,
     colors: Res<Colors>,
 ) {
     let colors = colors.into_inner();
@@ -284,6 +463,26 @@ pub fn window_resized_event(
                 commands.entity(entity).despawn();
             }
         }
+        if let Some(entity) = chroma_entity.0.take() {
+            if commands.get_entity(entity).is_some() {
+                commands.entity(entity).despawn();
+            }
+        }
+        if let Some(entity) = oscilloscope_entity.0.take() {
+            if commands.get_entity(entity).is_some() {
+                commands.entity(entity).despawn();
+            }
+        }
+        if let Some(entity) = spectrogram_entity.0.take() {
+            if commands.get_entity(entity).is_some() {
+                commands.entity(entity).despawn();
+            }
+        }
+        if let Some(entity) = vectorscope_entity.0.take() {
+            if commands.get_entity(entity).is_some() {
+                commands.entity(entity).despawn();
+            }
+        }
 
         // Then spawn the new entity based on the current visualization type
         match *visualization_type {
@@ -379,6 +578,113 @@ pub fn window_resized_event(
                     .id();
                 wave_entity.0 = Some(new_entity);
             }
+            VisualizationType::Chroma => {
+                let chroma_material_handle = prepare_material!(
+                    ChromaMaterial,
+                    chroma_material,
+                    event.width,
+                    event.height,
+                    colors
+                );
+                let new_entity = commands
+                    .spawn(MaterialMesh2dBundle {
+                        mesh: Mesh2dHandle(mesh_handle),
+                        material: chroma_material_handle,
+                        transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                        ..Default::default()
+                    })
+                    .id();
+                chroma_entity.0 = Some(new_entity);
+            }
+            VisualizationType::Oscilloscope => {
+                let oscilloscope_material_handle = prepare_material!(
+                    OscilloscopeMaterial,
+                    oscilloscope_material,
+                    event.width,
+                    event.height,
+                    colors
+                );
+                let new_entity = commands
+                    .spawn(MaterialMesh2dBundle {
+                        mesh: Mesh2dHandle(mesh_handle),
+                        material: oscilloscope_material_handle,
+                        transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                        ..Default::default()
+                    })
+                    .id();
+                oscilloscope_entity.0 = Some(new_entity);
+            }
+            VisualizationType::Spectrogram => {
+                let spectrogram_material_handle = prepare_material!(
+                    SpectrogramMaterial,
+                    spectrogram_material,
+                    event.width,
+                    event.height,
+                    colors
+                );
+                let new_entity = commands
+                    .spawn(MaterialMesh2dBundle {
+                        mesh: Mesh2dHandle(mesh_handle),
+                        material: spectrogram_material_handle,
+                        transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                        ..Default::default()
+                    })
+                    .id();
+                spectrogram_entity.0 = Some(new_entity);
+            }
+            VisualizationType::Vectorscope => {
+                let vectorscope_material_handle = prepare_material!(
+                    VectorscopeMaterial,
+                    vectorscope_material,
+                    event.width,
+                    event.height,
+                    colors
+                );
+                let new_entity = commands
+                    .spawn(MaterialMesh2dBundle {
+                        mesh: Mesh2dHandle(mesh_handle),
+                        material: vectorscope_material_handle,
+                        transform: Transform::from_xyz(0.0, 0.0, 0.0),
+                        ..Default::default()
+                    })
+                    .id();
+                vectorscope_entity.0 = Some(new_entity);
+            }
         }
     }
 }
+
+// Passes the analysis uniforms to the material of the spawned visualizer.
+// Runs every frame, so a visualizer spawned since the last analyzed frame
+// does not start from zero.
+pub fn visualizer_uniform_system(
+    features: Res<SpectralFeatures>,
+    bands: Res<BandEnergies>,
+    config: Res<CfgResource>,
+    visualization_type: Res<VisualizationType>,
+    mut materials: VisualizerMaterials,
+    mut extra_visualizers: ExtraVisualizers,
+) {
+    let spectral_features = features.uniform(config.0.frequency_min, config.0.frequency_max);
+    let band_energies = bands.uniform();
+
+    macro_rules! set_uniforms {
+        ($assets:expr) => {
+            for (_, material) in $assets.iter_mut() {
+                material.spectral_features = spectral_features;
+                material.band_energies = band_energies;
+            }
+        };
+    }
+    match *visualization_type {
+        VisualizationType::Bar => set_uniforms!(materials.bar_material),
+        VisualizationType::String => set_uniforms!(materials.string_material),
+        VisualizationType::CircleSplit => set_uniforms!(materials.circle_split_material),
+        VisualizationType::Polygon => set_uniforms!(materials.polygon_material),
+        VisualizationType::Wave => set_uniforms!(materials.wave_material),
+        VisualizationType::Chroma => set_uniforms!(extra_visualizers.chroma_material),
+        VisualizationType::Oscilloscope => set_uniforms!(extra_visualizers.oscilloscope_material),
+        VisualizationType::Spectrogram => set_uniforms!(extra_visualizers.spectrogram_material),
+        VisualizationType::Vectorscope => set_uniforms!(extra_visualizers.vectorscope_material),
+    }
+}
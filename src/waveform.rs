@@ -0,0 +1,187 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use crate::audio_capture::AudioProcessedEvent;
+use crate::cfg::TriggerMode;
+use crate::materials::OscilloscopeMaterial;
+use crate::tempo::TempoEstimate;
+use crate::visualization::VisualizationType;
+use crate::CfgResource;
+
+pub const MIN_WAVEFORM_POINTS: usize = 64;
+pub const MAX_WAVEFORM_POINTS: usize = 4096;
+pub const MAX_WAVEFORM_WINDOW_MS: f32 = 500.0;
+
+// How far before the newest full window the trigger may look for an edge,
+// which covers one period down to 20 Hz
+const TRIGGER_SEARCH_SECONDS: f32 = 0.05;
+// The signal has to drop this far below the trigger level before the next
+// rising edge counts, so noise around the level does not retrigger
+const TRIGGER_HYSTERESIS: f32 = 0.01;
+
+/// The most recent time-domain window of both channels, downsampled to
+/// `waveform_points` values between -1.0 and 1.0 and aligned to the trigger.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct Waveform {
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+    /// Whether the window starts at a trigger edge rather than free-running
+    pub triggered: bool,
+}
+
+#[derive(Resource, Default)]
+pub struct WaveformBuffer {
+    sample_rate: u32,
+    left: VecDeque<f32>,
+    right: VecDeque<f32>,
+}
+
+impl WaveformBuffer {
+    fn push(&mut self, event: &AudioProcessedEvent) {
+        if event.sample_rate != self.sample_rate {
+            self.sample_rate = event.sample_rate;
+            self.left.clear();
+            self.right.clear();
+        }
+        self.left.extend(event.left.iter().copied());
+        self.right.extend(event.right.iter().copied());
+
+        let capacity = self.capacity();
+        for channel in [&mut self.left, &mut self.right] {
+            let excess = channel.len().saturating_sub(capacity);
+            channel.drain(..excess);
+        }
+    }
+
+    // Longest window plus the trigger search span
+    fn capacity(&self) -> usize {
+        (self.sample_rate as f32 * (MAX_WAVEFORM_WINDOW_MS / 1000.0 + TRIGGER_SEARCH_SECONDS))
+            .ceil() as usize
+    }
+
+    // Start of the newest window of `window_len` samples that begins on a
+    // rising edge through `level`
+    fn find_trigger(&self, window_len: usize, level: f32) -> Option<usize> {
+        let len = self.left.len().min(self.right.len());
+        let latest_start = len.checked_sub(window_len)?;
+        let search_len = (self.sample_rate as f32 * TRIGGER_SEARCH_SECONDS) as usize + window_len;
+        let earliest_start = latest_start.saturating_sub(search_len).max(1);
+
+        let mono = |i: usize| (self.left[i] + self.right[i]) * 0.5;
+        let mut armed = false;
+        let mut trigger = None;
+        // Walk forwards so the hysteresis sees the samples in order
+        for i in earliest_start..=latest_start {
+            if mono(i - 1) < level - TRIGGER_HYSTERESIS {
+                armed = true;
+            }
+            if armed && mono(i - 1) < level && mono(i) >= level {
+                trigger = Some(i);
+                armed = false;
+            }
+        }
+        trigger
+    }
+
+    fn window(&self, window_len: usize, trigger_mode: TriggerMode, level: f32) -> (usize, bool) {
+        let len = self.left.len().min(self.right.len());
+        let free_start = len.saturating_sub(window_len);
+        match trigger_mode {
+            TriggerMode::Free => (free_start, false),
+            TriggerMode::RisingEdge => match self.find_trigger(window_len, level) {
+                Some(start) => (start, true),
+                None => (free_start, false),
+            },
+        }
+    }
+}
+
+// Reduces a window to `points` values written into `output`, keeping the
+// most extreme sample of each segment so short peaks survive the downsampling
+fn downsample(
+    samples: &VecDeque<f32>,
+    start: usize,
+    window_len: usize,
+    points: usize,
+    output: &mut Vec<f32>,
+) {
+    output.clear();
+    let end = (start + window_len).min(samples.len());
+    if end <= start {
+        output.resize(points, 0.0);
+        return;
+    }
+    let window_len = end - start;
+
+    output.extend((0..points).map(|point| {
+        let segment_start = start + point * window_len / points;
+        let segment_end = (start + (point + 1) * window_len / points).max(segment_start + 1);
+        samples
+            .range(segment_start..segment_end.min(end))
+            .copied()
+            .fold(0.0f32, |extreme, sample| {
+                if sample.abs() > extreme.abs() {
+                    sample
+                } else {
+                    extreme
+                }
+            })
+            .clamp(-1.0, 1.0)
+    }));
+}
+
+pub fn waveform_system(
+    mut sample_events: EventReader<AudioProcessedEvent>,
+    mut buffer: ResMut<WaveformBuffer>,
+    mut waveform: ResMut<Waveform>,
+    config: Res<CfgResource>,
+) {
+    let mut received = false;
+    for event in sample_events.read() {
+        buffer.push(event);
+        received = true;
+    }
+    if !received {
+        return;
+    }
+
+    let config = &config.0;
+    let window_ms = config.waveform_window_ms.clamp(1.0, MAX_WAVEFORM_WINDOW_MS);
+    let window_len = ((buffer.sample_rate as f32 * window_ms / 1000.0) as usize).max(1);
+    let points = config
+        .waveform_points
+        .clamp(MIN_WAVEFORM_POINTS, MAX_WAVEFORM_POINTS);
+
+    let (start, triggered) = buffer.window(window_len, config.trigger_mode, config.trigger_level);
+    downsample(&buffer.left, start, window_len, points, &mut waveform.left);
+    downsample(
+        &buffer.right,
+        start,
+        window_len,
+        points,
+        &mut waveform.right,
+    );
+    waveform.triggered = triggered;
+}
+
+pub fn oscilloscope_material_system(
+    waveform: Res<Waveform>,
+    tempo: Res<TempoEstimate>,
+    config: Res<CfgResource>,
+    visualization_type: Res<VisualizationType>,
+    mut oscilloscope_material: ResMut<Assets<OscilloscopeMaterial>>,
+) {
+    if !matches!(*visualization_type, VisualizationType::Oscilloscope) {
+        return;
+    }
+
+    for (_, material) in oscilloscope_material.iter_mut() {
+        if !waveform.left.is_empty() {
+            material.left_data.clone_from(&waveform.left);
+            material.right_data.clone_from(&waveform.right);
+        }
+        material.line_width = config.0.scope_line_width;
+        material.glow = config.0.scope_glow;
+        material.beat_phase = tempo.beat_phase;
+    }
+}
@@ -0,0 +1,218 @@
+use crate::audio_processing::bucket_center_frequency;
+use crate::cfg::{AnalyzerMode, TiltPreset, WeightingCurve};
+use crate::loudness::k_weighting;
+use crate::MyConfig;
+
+/// Pivot of the tilt presets.
+pub const DEFAULT_TILT_PIVOT_HZ: f32 = 1000.0;
+
+// How much the magnitude of pink noise summed over a bucket grows per octave,
+// the bucket width doubling while the spectrum density halves
+const MAGNITUDE_SUM_PINK_SLOPE: f32 = 3.010_3;
+
+/// Gain of a weighting curve at `frequency` in dB. A, C and 468 are
+/// normalized to 0 dB at 1 kHz, K is the BS.1770 pre-filter as used by the
+/// loudness meter.
+pub fn curve_gain_db(curve: WeightingCurve, frequency: f32, sample_rate: u32) -> f32 {
+    let f = frequency as f64;
+    if f <= 0.0 {
+        return f32::NEG_INFINITY;
+    }
+    let f2 = f * f;
+
+    let gain_db = match curve {
+        WeightingCurve::Flat => 0.0,
+        WeightingCurve::A => {
+            let response = 12194.0f64.powi(2) * f2 * f2
+                / ((f2 + 20.6f64.powi(2))
+                    * ((f2 + 107.7f64.powi(2)) * (f2 + 737.9f64.powi(2))).sqrt()
+                    * (f2 + 12194.0f64.powi(2)));
+            20.0 * response.log10() + 2.0
+        }
+        WeightingCurve::C => {
+            let response =
+                12194.0f64.powi(2) * f2 / ((f2 + 20.6f64.powi(2)) * (f2 + 12194.0f64.powi(2)));
+            20.0 * response.log10() + 0.06
+        }
+        WeightingCurve::K => {
+            // The digital filters are only defined below Nyquist
+            let f = f.min(sample_rate as f64 * 0.499);
+            let response: f64 = k_weighting(sample_rate)
+                .iter()
+                .map(|filter| filter.magnitude(f, sample_rate))
+                .product();
+            20.0 * response.log10()
+        }
+        WeightingCurve::Itu468 => {
+            let h1 = -4.737338981378384e-24 * f2 * f2 * f2 + 2.043828333606125e-15 * f2 * f2
+                - 1.363894795463638e-7 * f2
+                + 1.0;
+            let h2 = 1.306612257412824e-19 * f2 * f2 * f - 2.118150887518656e-11 * f2 * f
+                + 5.559488023498642e-4 * f;
+            let response = 1.246332637532143e-4 * f / (h1 * h1 + h2 * h2).sqrt();
+            18.2 + 20.0 * response.log10()
+        }
+    };
+    gain_db as f32
+}
+
+/// Gain in dB of a straight tilt through the pivot frequency, which it
+/// leaves unchanged.
+pub fn tilt_gain_db(frequency: f32, db_per_octave: f32, pivot_hz: f32) -> f32 {
+    if frequency <= 0.0 || pivot_hz <= 0.0 {
+        return 0.0;
+    }
+    db_per_octave * (frequency / pivot_hz).log2()
+}
+
+/// Tilt that shows pink noise flat with the analyzer of `config`. Summed bin
+/// magnitudes rise with the bucket width, while the power sum of the
+/// multi-resolution FFT and the constant-Q bins already show it flat.
+pub fn pink_noise_tilt(config: &MyConfig) -> f32 {
+    match config.analyzer {
+        AnalyzerMode::Fft if !config.multi_resolution => -MAGNITUDE_SUM_PINK_SLOPE,
+        _ => 0.0,
+    }
+}
+
+/// Weighting curve and tilt, with the bucket layout their gains are
+/// evaluated on.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Weighting {
+    pub curve: WeightingCurve,
+    pub tilt_db_per_octave: f32,
+    pub tilt_pivot_hz: f32,
+    pub frequency_min: f32,
+    pub frequency_max: f32,
+}
+
+impl Weighting {
+    pub fn new(config: &MyConfig) -> Self {
+        Weighting {
+            curve: config.weighting,
+            tilt_db_per_octave: match config.tilt_preset {
+                TiltPreset::Off => 0.0,
+                TiltPreset::PinkNoiseFlat => pink_noise_tilt(config),
+                TiltPreset::Custom => config.weighting_tilt,
+            },
+            tilt_pivot_hz: config.tilt_pivot_hz,
+            frequency_min: config.frequency_min,
+            frequency_max: config.frequency_max,
+        }
+    }
+
+    /// Scales the bucket magnitudes by the curve and tilt, each bucket taking
+    /// the gain at its center frequency.
+    pub fn apply(&self, buckets: &mut [f32], sample_rate: u32) {
+        if self.curve == WeightingCurve::Flat && self.tilt_db_per_octave == 0.0 {
+            return;
+        }
+
+        let num_buckets = buckets.len();
+        for (i, bucket) in buckets.iter_mut().enumerate() {
+            let frequency =
+                bucket_center_frequency(i, num_buckets, self.frequency_min, self.frequency_max);
+            let gain_db = curve_gain_db(self.curve, frequency, sample_rate)
+                + tilt_gain_db(frequency, self.tilt_db_per_octave, self.tilt_pivot_hz);
+            *bucket *= 10f32.powf(gain_db / 20.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft::{BinSum, Bucketizer, FftAnalyzer, HannWindow};
+    use realfft::num_complex::Complex;
+    use realfft::RealFftPlanner;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const BLOCK_LEN: usize = 2048;
+
+    // Blocks of pink noise with the same seed every run, synthesized with
+    // 1/f power and pseudo-random phases
+    fn pink_noise_blocks(count: usize) -> Vec<Vec<f32>> {
+        let inverse = RealFftPlanner::<f32>::new().plan_fft_inverse(BLOCK_LEN);
+        let mut seed: u32 = 0x1234_5678;
+        let mut random_phase = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            seed as f32 / u32::MAX as f32 * std::f32::consts::TAU
+        };
+
+        (0..count)
+            .map(|_| {
+                let mut spectrum = inverse.make_input_vec();
+                for (bin, value) in spectrum.iter_mut().enumerate() {
+                    if bin == 0 || bin == BLOCK_LEN / 2 {
+                        continue;
+                    }
+                    *value = Complex::from_polar((bin as f32).powf(-0.5), random_phase());
+                }
+                let mut samples = inverse.make_output_vec();
+                inverse.process(&mut spectrum, &mut samples).unwrap();
+                samples
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pink_noise_preset_flattens_fft_buckets() {
+        let config = MyConfig {
+            analyzer: AnalyzerMode::Fft,
+            multi_resolution: false,
+            tilt_preset: TiltPreset::PinkNoiseFlat,
+            ..MyConfig::default()
+        };
+        let frequency_range = (config.frequency_min, config.frequency_max);
+        let bin_hz = SAMPLE_RATE as f32 / BLOCK_LEN as f32;
+
+        let mut window = HannWindow::default();
+        let mut fft = FftAnalyzer::default();
+        let mut bucketizer = Bucketizer::default();
+        let mut magnitudes = Vec::new();
+        let mut buckets = vec![0.0; config.num_buckets];
+        let mut average = vec![0.0; config.num_buckets];
+        for mut samples in pink_noise_blocks(32) {
+            window.apply(&mut samples);
+            fft.magnitudes(&samples, &mut magnitudes).unwrap();
+            assert!(bucketizer.bucketize(
+                &magnitudes,
+                bin_hz,
+                frequency_range,
+                BinSum::Magnitude,
+                &mut buckets,
+            ));
+            for (average, bucket) in average.iter_mut().zip(&buckets) {
+                *average += bucket;
+            }
+        }
+        Weighting::new(&config).apply(&mut average, SAMPLE_RATE);
+
+        // Least-squares slope of the level over the octaves
+        let points: Vec<(f32, f32)> = average
+            .iter()
+            .enumerate()
+            .map(|(i, &level)| {
+                let frequency = bucket_center_frequency(
+                    i,
+                    average.len(),
+                    config.frequency_min,
+                    config.frequency_max,
+                );
+                (frequency.log2(), 20.0 * level.log10())
+            })
+            .filter(|&(octave, _)| (100f32.log2()..=10_000f32.log2()).contains(&octave))
+            .collect();
+        let count = points.len() as f32;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f32>() / count;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f32>() / count;
+        let covariance: f32 = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let variance: f32 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        let slope = covariance / variance;
+
+        assert!(slope.abs() < 0.3, "pink noise slope {slope} dB/octave");
+    }
+}